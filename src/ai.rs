@@ -0,0 +1,226 @@
+use serde::{Serialize, Deserialize};
+
+use crate::movegen;
+use crate::replay::Input;
+use crate::tetrimino::Tetrimino;
+use crate::tetris_struct::Tetris;
+
+/// How much every feature of the map counts when evaluating it, the higher the better.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Weights {
+    // sum of the heights of the columns
+    pub aggregate_height: f32,
+    // empty cases with a mino above them
+    pub holes: f32,
+    // sum of the height differences between neighbouring columns
+    pub bumpiness: f32,
+    // sum of the depths of the columns lower than both of their neighbours
+    pub wells: f32,
+    // changes from an empty case to a mino along the lines, the walls counting as minos
+    pub row_transitions: f32,
+    // lines cleared by the placements, and cleared with a T-spin on top of that
+    pub lines: f32,
+    pub t_spin_lines: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            aggregate_height: -0.51,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.1,
+            row_transitions: -0.1,
+            lines: 0.76,
+            t_spin_lines: 0.5,
+        }
+    }
+}
+
+impl Weights {
+    fn clears(&self, lines: usize, t_spin: bool) -> f32 {
+        let t_spin_lines = if t_spin { self.t_spin_lines } else { 0.0 };
+        (self.lines + t_spin_lines) * lines as f32
+    }
+
+    pub fn evaluate(&self, game_map: &[Vec<u8>]) -> f32 {
+        let width = game_map[0].len();
+        let heights = (0..width)
+            .map(|x| game_map.len() - game_map.iter().position(|line| line[x] != 0).unwrap_or(game_map.len()))
+            .collect::<Vec<_>>();
+
+        let aggregate_height = heights.iter().sum::<usize>();
+        let holes = (0..width)
+            .map(|x| game_map.iter().skip(game_map.len() - heights[x]).filter(|line| line[x] == 0).count())
+            .sum::<usize>();
+        let bumpiness = heights.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum::<usize>();
+        let wells = (0..width)
+            .map(|x| {
+                let left = if x == 0 { game_map.len() } else { heights[x - 1] };
+                let right = heights.get(x + 1).cloned().unwrap_or(game_map.len());
+                left.min(right).saturating_sub(heights[x])
+            })
+            .sum::<usize>();
+        let row_transitions = game_map.iter()
+            .map(|line| {
+                let mut filled = true;
+                let mut transitions = 0;
+                for case in line.iter().chain(std::iter::once(&1)) {
+                    if (*case != 0) != filled {
+                        filled = !filled;
+                        transitions += 1;
+                    }
+                }
+                transitions
+            })
+            .sum::<usize>();
+
+        self.aggregate_height * aggregate_height as f32 + self.holes * holes as f32 +
+            self.bumpiness * bumpiness as f32 + self.wells * wells as f32 +
+            self.row_transitions * row_transitions as f32
+    }
+}
+
+/// Where the AI is taking the current piece.
+struct Target {
+    // number of pieces made permanent when it was chosen, it's only valid for that piece
+    nb_pieces: u32,
+    // the piece to place is the held one, or the next one if no piece was held
+    hold: bool,
+    cells: Vec<(usize, usize)>,
+    // the placement was scored as a T-spin, the way without one fills the same cells
+    t_spin: bool,
+}
+
+/// A player choosing the placement of every piece with an evaluation of the maps it leads to, and
+/// playing it with the inputs of a human player.
+pub struct Ai {
+    pub weights: Weights,
+    // number of pieces of the preview placed after the current one to choose its placement
+    pub lookahead: usize,
+    // ticks between two inputs, 0 to place the pieces at once
+    pub delay: u32,
+    target: Option<Target>,
+    wait: u32,
+}
+
+impl Ai {
+    pub fn new(lookahead: usize, delay: u32) -> Ai {
+        Ai {
+            weights: Weights::default(),
+            lookahead,
+            delay,
+            target: None,
+            wait: 0,
+        }
+    }
+
+    /// Plays the inputs due by the current tick. Returns true if the current piece was made
+    /// permanent.
+    pub fn update(&mut self, tetris: &mut Tetris) -> bool {
+        if tetris.current_piece.is_none() {
+            return false;
+        }
+        if self.wait > 0 {
+            self.wait -= 1;
+            return false;
+        }
+        self.wait = self.delay.saturating_sub(1);
+
+        if !matches!(self.target, Some(ref target) if target.nb_pieces == tetris.nb_pieces) {
+            self.target = self.choose(tetris);
+        }
+        let target = match self.target {
+            Some(ref mut target) => target,
+            None => return tetris.apply(Input::HardDrop, false),
+        };
+        if target.hold {
+            target.hold = false;
+            tetris.apply(Input::Hold, false);
+            if self.delay > 0 {
+                return false;
+            }
+        }
+
+        let inputs = match tetris.current_piece {
+            Some(ref piece) => movegen::placements(&tetris.game_map, piece).into_iter()
+                .filter(|placement| placement.cells == target.cells)
+                .min_by_key(|placement| placement.t_spin != target.t_spin)
+                .map(|placement| placement.inputs),
+            None => return false,
+        };
+        let inputs = match inputs {
+            Some(inputs) => inputs,
+            // the piece went past the way to the placement, another one is chosen on the next tick
+            None => {
+                self.target = None;
+                return false;
+            }
+        };
+        // the way is looked for again before every input, the piece may have fallen in between
+        let nb_inputs = if self.delay > 0 { 1 } else { inputs.len() };
+        inputs.into_iter().take(nb_inputs).any(|input| tetris.apply(input, false))
+    }
+
+    /// The best placement for the current piece, or the held one, with the pieces of the preview
+    /// placed after it.
+    fn choose(&self, tetris: &Tetris) -> Option<Target> {
+        let piece_set = &tetris.rules.piece_set;
+        let create = |name: char| piece_set.position(name)
+            .map(|index| piece_set.create(index).scaled(tetris.rules.scale()));
+        let queue = tetris.preview(self.lookahead).into_iter().filter_map(create).collect::<Vec<_>>();
+
+        let mut candidates = vec![(false, tetris.current_piece.clone()?, &queue[..])];
+        if tetris.can_hold() {
+            match tetris.held_piece() {
+                Some(name) => candidates.extend(create(name).map(|piece| (true, piece, &queue[..]))),
+                None => candidates.extend(queue.first().map(|piece| (true, piece.clone(), &queue[1..]))),
+            }
+        }
+
+        let mut best: Option<(f32, Target)> = None;
+        for (hold, piece, queue) in candidates {
+            for placement in movegen::placements(&tetris.game_map, &piece) {
+                let (game_map, lines) = lock(&tetris.game_map, &placement.cells);
+                let score = self.weights.clears(lines, placement.t_spin) + self.search(&game_map, queue);
+                if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                    best = Some((score, Target { nb_pieces: tetris.nb_pieces, hold, cells: placement.cells,
+                                                 t_spin: placement.t_spin }));
+                }
+            }
+        }
+        best.map(|(_, target)| target)
+    }
+
+    /// Evaluation of the best map reached by placing the pieces one after the other.
+    fn search(&self, game_map: &[Vec<u8>], pieces: &[Tetrimino]) -> f32 {
+        let (piece, pieces) = match pieces.split_first() {
+            Some(pieces) => pieces,
+            None => return self.weights.evaluate(game_map),
+        };
+
+        movegen::placements(game_map, piece).iter()
+            .map(|placement| {
+                let (game_map, lines) = lock(game_map, &placement.cells);
+                self.weights.clears(lines, placement.t_spin) + self.search(&game_map, pieces)
+            })
+            .fold(f32::MIN, f32::max)
+    }
+}
+
+/// The map with the cases taken by a piece, once the complete lines are cleared, and the number
+/// of lines cleared.
+fn lock(game_map: &[Vec<u8>], cells: &[(usize, usize)]) -> (Vec<Vec<u8>>, usize) {
+    let mut game_map = game_map.to_vec();
+    for &(x, y) in cells.iter() {
+        game_map[y][x] = 1;
+    }
+
+    let height = game_map.len();
+    game_map.retain(|line| line.contains(&0));
+    let lines = height - game_map.len();
+    let mut cleared = vec![vec![0; game_map.first().map_or(cells.len(), Vec::len)]; lines];
+    cleared.append(&mut game_map);
+    (cleared, lines)
+}
@@ -0,0 +1,575 @@
+use std::io::{BufRead, Write};
+use std::thread;
+
+use serde::{Serialize, Deserialize};
+
+use crate::ai::Weights;
+use crate::game_mode::GameMode;
+use crate::movegen::{self, Placement};
+use crate::replay::Input;
+use crate::rules::Rules;
+use crate::tetris_struct::Tetris;
+
+pub const ENV_DEFAULT_PREVIEW: usize = 5;
+
+/// How the agent plays.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSpace {
+    // one key or none per engine tick
+    Keys,
+    // one of the placements of the observation per piece
+    Placements,
+}
+
+/// What the agent does on a step.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    // lets a tick go by without pressing a key
+    Wait,
+    Key(Input),
+    // index in the placements of the last observation
+    Place(usize),
+}
+
+/// How much the agent earns for what happens during a step.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reward {
+    // per point of score
+    pub score: f32,
+    // per line cleared
+    pub lines: f32,
+    // per piece made permanent, to reward surviving
+    pub pieces: f32,
+    // once when the stack tops out
+    pub game_over: f32,
+    // for an action which isn't in the action space or can't be played, the game doesn't change
+    pub invalid_action: f32,
+    // the change of the evaluation of the map with these weights, for a denser reward
+    pub shaping: Option<Weights>,
+}
+
+impl Default for Reward {
+    fn default() -> Reward {
+        Reward {
+            score: 0.0,
+            lines: 1.0,
+            pieces: 0.0,
+            game_over: -1.0,
+            invalid_action: -0.1,
+            shaping: None,
+        }
+    }
+}
+
+/// The game played by the environments.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvConfig {
+    pub mode: GameMode,
+    pub rules: Rules,
+    pub action_space: ActionSpace,
+    pub reward: Reward,
+    // pieces of the preview in the observations
+    pub preview: usize,
+    // steps after which the game is cut short, None to play it until it's over
+    pub max_steps: Option<u32>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> EnvConfig {
+        EnvConfig {
+            mode: GameMode::Marathon,
+            rules: Rules::default(),
+            action_space: ActionSpace::Placements,
+            reward: Reward::default(),
+            preview: ENV_DEFAULT_PREVIEW,
+            max_steps: None,
+        }
+    }
+}
+
+/// The current piece and where it is.
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Piece {
+    pub name: char,
+    pub x: isize,
+    pub y: usize,
+    pub state: u8,
+}
+
+/// A placement the agent can choose with the high-level actions.
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Choice {
+    // the piece placed is the held one, or the next one if no piece was held
+    pub hold: bool,
+    pub x: isize,
+    pub y: usize,
+    pub state: u8,
+    pub cells: Vec<(usize, usize)>,
+    pub t_spin: bool,
+}
+
+/// What the agent sees of the game.
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Observation {
+    // true for the cases taken, from the top line
+    pub board: Vec<Vec<bool>>,
+    // None between two pieces
+    pub piece: Option<Piece>,
+    pub hold: Option<char>,
+    pub can_hold: bool,
+    pub queue: Vec<char>,
+    // only listed with the placements action space
+    pub placements: Vec<Choice>,
+}
+
+/// How the game went, after a step.
+#[derive(Clone, Serialize)]
+pub struct Info {
+    pub score: u32,
+    pub lines: u32,
+    pub pieces: u32,
+    pub attack: u32,
+    pub frame: u64,
+    pub steps: u32,
+    pub invalid_action: bool,
+}
+
+/// A game played by an agent, one step at a time.
+pub struct Env {
+    pub config: EnvConfig,
+    tetris: Tetris,
+    seed: u64,
+    steps: u32,
+    // evaluation of the map with the weights of the shaping of the reward
+    evaluation: f32,
+    // placements of the last observation
+    choices: Vec<(bool, Placement)>,
+}
+
+impl Env {
+    /// An environment which has to be reset before its first step.
+    pub fn new(config: EnvConfig) -> Env {
+        let tetris = Tetris::with_seed(config.mode.clone(), config.rules.clone(), 0);
+
+        Env {
+            config,
+            tetris,
+            seed: 0,
+            steps: 0,
+            evaluation: 0.0,
+            choices: Vec::new(),
+        }
+    }
+
+    /// Starts a new game, the same one for the same seed.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.tetris = Tetris::with_seed(self.config.mode.clone(), self.config.rules.clone(), seed);
+        self.seed = seed;
+        self.steps = 0;
+        self.wait_for_piece();
+        self.evaluation = self.evaluate();
+        self.observe()
+    }
+
+    /// Plays the action, and returns what the agent sees after it, the reward it earned, whether
+    /// the game is over and how the game went.
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool, Info) {
+        if self.is_done() {
+            return (self.observe(), 0.0, true, self.info(false));
+        }
+        let (score, lines, pieces) = (self.tetris.score, self.tetris.nb_lines, self.tetris.nb_pieces);
+        self.steps += 1;
+
+        let valid = match (self.config.action_space, action) {
+            (ActionSpace::Keys, Action::Wait) => {
+                self.tetris.tick();
+                true
+            }
+            (ActionSpace::Keys, Action::Key(input)) if input != Input::Undo && input != Input::Redo => {
+                self.tetris.apply(input, false);
+                self.tetris.tick();
+                true
+            }
+            (ActionSpace::Placements, Action::Place(index)) if index < self.choices.len() => {
+                self.place(index);
+                self.wait_for_piece();
+                true
+            }
+            _ => false,
+        };
+
+        let reward = &self.config.reward;
+        let mut earned = reward.score * (self.tetris.score - score) as f32 +
+            reward.lines * (self.tetris.nb_lines - lines) as f32 +
+            reward.pieces * (self.tetris.nb_pieces - pieces) as f32;
+        if !valid {
+            earned += reward.invalid_action;
+        }
+        if self.tetris.is_game_over() {
+            earned += reward.game_over;
+        }
+        let evaluation = self.evaluate();
+        earned += evaluation - self.evaluation;
+        self.evaluation = evaluation;
+
+        (self.observe(), earned, self.is_done(), self.info(!valid))
+    }
+
+    fn is_done(&self) -> bool {
+        self.tetris.is_game_over() || self.tetris.is_finished() ||
+            self.config.max_steps.is_some_and(|max_steps| self.steps >= max_steps)
+    }
+
+    /// Plays one of the placements of the last observation with the inputs of a player.
+    fn place(&mut self, index: usize) {
+        let (hold, ref target) = self.choices[index];
+        if hold {
+            self.tetris.apply(Input::Hold, false);
+        }
+
+        // the held piece was listed as it spawns, its way is looked for again once it did
+        let inputs = match self.tetris.current_piece {
+            Some(ref piece) => movegen::placements(&self.tetris.game_map, piece).into_iter()
+                .filter(|placement| placement.cells == target.cells)
+                .min_by_key(|placement| placement.t_spin != target.t_spin)
+                .map_or(vec![Input::HardDrop], |placement| placement.inputs),
+            None => return,
+        };
+        for input in inputs {
+            if self.tetris.apply(input, false) {
+                break;
+            }
+        }
+    }
+
+    /// Lets the ticks go by until the next piece spawns.
+    fn wait_for_piece(&mut self) {
+        while self.tetris.current_piece.is_none() && !self.tetris.is_game_over() && !self.tetris.is_finished() {
+            self.tetris.tick();
+        }
+    }
+
+    fn evaluate(&self) -> f32 {
+        match self.config.reward.shaping {
+            Some(ref weights) => weights.evaluate(&self.tetris.game_map),
+            None => 0.0,
+        }
+    }
+
+    fn observe(&mut self) -> Observation {
+        let tetris = &self.tetris;
+
+        self.choices.clear();
+        if self.config.action_space == ActionSpace::Placements && !self.is_done() {
+            if let Some(ref piece) = tetris.current_piece {
+                let piece_set = &tetris.rules.piece_set;
+                let mut pieces = vec![(false, piece.clone())];
+                if tetris.can_hold() {
+                    let name = tetris.held_piece().or_else(|| tetris.preview(1).first().cloned());
+                    pieces.extend(name.and_then(|name| piece_set.position(name))
+                        .map(|index| (true, piece_set.create(index).scaled(tetris.rules.scale()))));
+                }
+                for (hold, piece) in pieces {
+                    self.choices.extend(movegen::placements(&tetris.game_map, &piece).into_iter()
+                        .map(|placement| (hold, placement)));
+                }
+            }
+        }
+
+        Observation {
+            board: tetris.game_map.iter().map(|line| line.iter().map(|case| *case != 0).collect()).collect(),
+            piece: tetris.current_piece.as_ref().map(|piece| Piece {
+                name: piece.name,
+                x: piece.x,
+                y: piece.y,
+                state: piece.current_state,
+            }),
+            hold: tetris.held_piece(),
+            can_hold: tetris.can_hold(),
+            queue: tetris.preview(self.config.preview),
+            placements: self.choices.iter()
+                .map(|(hold, placement)| Choice {
+                    hold: *hold,
+                    x: placement.x,
+                    y: placement.y,
+                    state: placement.state,
+                    cells: placement.cells.clone(),
+                    t_spin: placement.t_spin,
+                })
+                .collect(),
+        }
+    }
+
+    fn info(&self, invalid_action: bool) -> Info {
+        Info {
+            score: self.tetris.score,
+            lines: self.tetris.nb_lines,
+            pieces: self.tetris.nb_pieces,
+            attack: self.tetris.clear_stats.attack,
+            frame: self.tetris.frame,
+            steps: self.steps,
+            invalid_action,
+        }
+    }
+}
+
+/// Environments stepped together, each one on the threads available.
+pub struct VecEnv {
+    pub envs: Vec<Env>,
+}
+
+impl VecEnv {
+    pub fn new(config: EnvConfig, nb_envs: usize) -> VecEnv {
+        VecEnv {
+            envs: (0..nb_envs).map(|_| Env::new(config.clone())).collect(),
+        }
+    }
+
+    /// Resets every environment, with the seed plus its index.
+    pub fn reset(&mut self, seed: u64) -> Vec<Observation> {
+        self.envs.iter_mut().enumerate().map(|(index, env)| env.reset(seed + index as u64)).collect()
+    }
+
+    /// Steps every environment with its action. The ones whose game is over are reset with their
+    /// seed plus the number of environments, the observation returned being the first of the new
+    /// game and the info the last of the old one.
+    pub fn step(&mut self, actions: &[Action]) -> Result<Vec<(Observation, f32, bool, Info)>, String> {
+        if actions.len() != self.envs.len() {
+            return Err(format!("{} actions for {} environments", actions.len(), self.envs.len()));
+        }
+        let nb_envs = self.envs.len() as u64;
+        let nb_threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk_size = self.envs.len().div_ceil(nb_threads).max(1);
+
+        Ok(thread::scope(|scope| {
+            let threads = self.envs.chunks_mut(chunk_size).zip(actions.chunks(chunk_size))
+                .map(|(envs, actions)| scope.spawn(move || {
+                    envs.iter_mut().zip(actions)
+                        .map(|(env, action)| {
+                            let (observation, reward, done, info) = env.step(*action);
+                            let observation = if done { env.reset(env.seed + nb_envs) } else { observation };
+                            (observation, reward, done, info)
+                        })
+                        .collect::<Vec<_>>()
+                }))
+                .collect::<Vec<_>>();
+            threads.into_iter().flat_map(|thread| thread.join().expect("an environment panicked")).collect()
+        }))
+    }
+}
+
+/// A message of the agent.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    // replaces the environments by ones playing another game, they have to be reset
+    Configure {
+        config: Box<EnvConfig>,
+    },
+    Reset {
+        #[serde(default)]
+        seed: u64,
+    },
+    Step {
+        actions: Vec<Action>,
+    },
+}
+
+#[derive(Serialize)]
+struct Transition {
+    observation: Observation,
+    reward: f32,
+    done: bool,
+    info: Info,
+}
+
+/// A message to the agent.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    Configured,
+    Observations {
+        observations: Vec<Observation>,
+    },
+    Steps {
+        steps: Vec<Transition>,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+/// Serves the environments to an agent in another process, with JSON messages, one per line,
+/// until its input is closed.
+pub fn serve(config: EnvConfig, nb_envs: usize, input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+    let mut envs = VecEnv::new(config, nb_envs);
+
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("couldn't read from the agent: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(Request::Configure { config }) => {
+                envs = VecEnv::new(*config, nb_envs);
+                Response::Configured
+            }
+            Ok(Request::Reset { seed }) => Response::Observations { observations: envs.reset(seed) },
+            Ok(Request::Step { actions }) => match envs.step(&actions) {
+                Ok(steps) => Response::Steps {
+                    steps: steps.into_iter()
+                        .map(|(observation, reward, done, info)| Transition { observation, reward, done, info })
+                        .collect(),
+                },
+                Err(reason) => Response::Error { reason },
+            },
+            Err(e) => Response::Error { reason: format!("invalid message: {}", e) },
+        };
+        let message = serde_json::to_string(&response).map_err(|e| e.to_string())?;
+        writeln!(output, "{}", message)
+            .and_then(|_| output.flush())
+            .map_err(|e| format!("couldn't write to the agent: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays with the placement of the given index among the ones observed, or the last one.
+    fn play(env: &mut Env, observation: &Observation, index: usize) -> (Observation, f32, bool, Info) {
+        env.step(Action::Place(index.min(observation.placements.len().saturating_sub(1))))
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_game() {
+        let mut a = Env::new(EnvConfig::default());
+        let mut b = Env::new(EnvConfig::default());
+        let (mut observation, other) = (a.reset(7), b.reset(7));
+        assert!(observation == other);
+
+        for step in 0..30 {
+            let index = step * 7 % observation.placements.len().max(1);
+            let (next, reward, done, _) = play(&mut a, &observation, index);
+            let (other, other_reward, other_done, _) = play(&mut b, &observation, index);
+            assert!(next == other);
+            assert_eq!((reward, done), (other_reward, other_done));
+            observation = next;
+        }
+        assert!(a.reset(8) != b.reset(7));
+    }
+
+    #[test]
+    fn a_placement_is_played_per_step() {
+        let mut env = Env::new(EnvConfig::default());
+        let mut observation = env.reset(1);
+
+        for pieces in 1..=10 {
+            // the lowest placement, to keep the stack flat
+            let (index, choice) = observation.placements.iter().enumerate()
+                .max_by_key(|(_, choice)| choice.cells.iter().map(|&(_, y)| y).min())
+                .unwrap();
+            let choice = choice.clone();
+            let (next, _, done, info) = play(&mut env, &observation, index);
+            assert!(!done);
+            assert_eq!(info.pieces, pieces);
+            for &(x, y) in choice.cells.iter() {
+                assert!(next.board[y][x] || info.lines > 0);
+            }
+            observation = next;
+        }
+
+        let (next, reward, _, info) = env.step(Action::Key(Input::Left));
+        assert!(info.invalid_action);
+        assert_eq!(reward, Reward::default().invalid_action);
+        assert!(next == observation);
+    }
+
+    #[test]
+    fn keys_are_played_one_tick_at_a_time() {
+        let mut env = Env::new(EnvConfig { action_space: ActionSpace::Keys, ..EnvConfig::default() });
+        let observation = env.reset(1);
+        assert!(observation.placements.is_empty());
+        let x = observation.piece.as_ref().unwrap().x;
+
+        let (observation, _, _, info) = env.step(Action::Key(Input::Left));
+        assert_eq!(observation.piece.unwrap().x, x - 1);
+        assert_eq!(info.frame, 2);
+        let (_, _, _, info) = env.step(Action::Key(Input::HardDrop));
+        assert_eq!(info.pieces, 1);
+        let (_, _, _, info) = env.step(Action::Place(0));
+        assert!(info.invalid_action);
+    }
+
+    #[test]
+    fn rewards_follow_the_config() {
+        let reward = Reward { lines: 0.0, pieces: 1.0, game_over: -5.0, ..Reward::default() };
+        let config = EnvConfig { reward, max_steps: Some(100), ..EnvConfig::default() };
+        let mut env = Env::new(config);
+        let mut observation = env.reset(3);
+
+        // stacking the pieces in the middle tops out fast
+        loop {
+            let middle = observation.placements.iter().position(|choice| !choice.hold && choice.x == 3).unwrap_or(0);
+            let (next, reward, done, info) = play(&mut env, &observation, middle);
+            if done {
+                assert_eq!(reward, 1.0 - 5.0);
+                assert!(info.steps < 100);
+                break;
+            }
+            assert_eq!(reward, 1.0);
+            observation = next;
+        }
+        let (_, reward, done, _) = env.step(Action::Place(0));
+        assert_eq!((reward, done), (0.0, true));
+    }
+
+    #[test]
+    fn finished_environments_are_reset_with_a_new_seed() {
+        let config = EnvConfig { max_steps: Some(3), ..EnvConfig::default() };
+        let mut envs = VecEnv::new(config, 5);
+        let observations = envs.reset(10);
+        assert!(observations[0] != observations[1]);
+
+        for step in 1..=3 {
+            let steps = envs.step(&[Action::Place(0); 5]).unwrap();
+            assert_eq!(steps.len(), 5);
+            for (observation, _, done, info) in steps {
+                assert_eq!(done, step == 3);
+                assert_eq!(info.steps, step);
+                assert_eq!(observation.board.iter().flatten().any(|case| *case), step < 3);
+            }
+        }
+        assert_eq!(envs.envs[0].seed, 15);
+        assert!(envs.step(&[Action::Wait]).is_err());
+    }
+
+    #[test]
+    fn environments_are_served_with_json() {
+        let requests = [
+            r#"{"type": "configure", "config": {"action_space": "keys", "reward": {"shaping": {}}}}"#,
+            r#"{"type": "reset", "seed": 4}"#,
+            r#"{"type": "step", "actions": ["wait", {"key": "HardDrop"}]}"#,
+            r#"{"type": "step", "actions": ["wait"]}"#,
+            "not a message",
+        ].join("\n");
+        let mut output = Vec::new();
+        serve(EnvConfig::default(), 2, requests.as_bytes(), &mut output).unwrap();
+
+        let responses = String::from_utf8(output).unwrap().lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        let kinds = responses.iter().map(|response| response["type"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(kinds, ["configured", "observations", "steps", "error", "error"]);
+        assert_eq!(responses[1]["observations"].as_array().unwrap().len(), 2);
+        assert_eq!(responses[1]["observations"][0]["board"].as_array().unwrap().len(), 16);
+        assert_eq!(responses[2]["steps"][1]["info"]["pieces"], 1);
+        // the dropped piece changes the evaluation of the map
+        assert_ne!(responses[2]["steps"][1]["reward"], 0.0);
+    }
+}
@@ -1,10 +1,64 @@
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Write, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use serde::{Serialize, Deserialize};
 
+use crate::history::{Record, CSV_HEADER};
+use crate::leaderboard::{Entry, Leaderboard, Leaderboards, Metric, LEADERBOARD_VERSION};
+use crate::piece_set::PieceSet;
+use crate::practice::Opener;
+use crate::profile::{Profiles, PROFILES_VERSION};
+use crate::puzzle::PuzzlePack;
+use crate::replay::Replay;
+use crate::tetris_struct::Tetris;
+
+const ZEN_SESSION_FILE: &str = "zen_session.json";
+const PUZZLES_DIR: &str = "assets/puzzles";
+const SOLVED_PUZZLES_FILE: &str = "solved_puzzles.txt";
+const OPENERS_FILE: &str = "assets/openers.json";
+const SAVE_FILE: &str = "save.json";
+// version of the save file format, to bump whenever the serialized game changes
+const SAVE_VERSION: u32 = 5;
+const REPLAYS_DIR: &str = "replays";
+const LEADERBOARD_FILE: &str = "leaderboard.json";
+const PROFILES_FILE: &str = "profiles.json";
+const HISTORY_FILE: &str = "history.jsonl";
+const HISTORY_CSV_FILE: &str = "history.csv";
+// the old record files didn't keep the rules, the games were played with the default ones
+const MIGRATED_RULES: &str = "standard";
+const APP_DIR: &str = "tetris";
+// how long to wait for another instance to release a lock, and after how long a lock is stale
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+const LOCK_STALE: Duration = Duration::from_secs(30);
+
+// set by --data-dir and --config-dir, read from then on
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Serialize, Deserialize)]
+struct SavedGame<T> {
+    version: u32,
+    game: T,
+}
+
+
+/// Writes into a temporary file first then renames it, so the file is never left half written.
 fn write_into_file(content: &str, file_name: &str) -> io::Result<()> {
-    let mut f = File::create(file_name)?;
-    f.write_all(content.as_bytes())
+    let temp_name = format!("{}.{}.tmp", file_name, std::process::id());
+    let result = File::create(&temp_name).and_then(|mut f| {
+        f.write_all(content.as_bytes())?;
+        f.sync_all()
+    }).and_then(|_| fs::rename(&temp_name, file_name));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_name);
+    }
+    result
 }
 
 fn read_from_file(file_name: &str) -> io::Result<String> {
@@ -15,23 +69,11 @@ fn read_from_file(file_name: &str) -> io::Result<String> {
     Ok(content)
 }
 
-fn slice_to_string(slice: &[u32]) -> String {
-    slice.iter().map(|highscore| highscore.to_string())
-        .collect::<Vec<String>>().join(" ")
+fn line_to_slice<T: FromStr>(line: &str) -> Vec<T> {
+    line.split(" ").filter_map(|nb| nb.trim().parse::<T>().ok()).collect()
 }
 
-fn line_to_slice(line: &str) -> Vec<u32> {
-    line.split(" ").filter_map(|nb| nb.parse::<u32>().ok()).collect()
-}
-
-pub fn save_highscores(highscores: &[u32], number_of_lines: &[u32]) -> bool {
-    let s_highscores = slice_to_string(highscores);
-    let s_number_of_lines = slice_to_string(number_of_lines);
-
-    write_into_file(&format!("{}\n{}\n", s_highscores, s_number_of_lines), "scores.txt").is_ok()
-}
-
-pub fn load_highscores_and_lines() -> Option<(Vec<u32>, Vec<u32>)> {
+fn load_highscores_and_lines() -> Option<(Vec<u32>, Vec<u32>)> {
     if let Ok(content) = read_from_file("scores.txt") {
         let mut lines = content.splitn(2, "\n")
             .map(|line| line_to_slice(line)).collect::<Vec<_>>();
@@ -41,4 +83,452 @@ pub fn load_highscores_and_lines() -> Option<(Vec<u32>, Vec<u32>)> {
             Some((highscores, number_lines))
         } else { None }
     } else { None }
-}
\ No newline at end of file
+}
+
+/// Exclusive access to a file among the running instances, released when dropped.
+struct FileLock {
+    path: String,
+}
+
+impl FileLock {
+    fn lock(file_name: &str) -> Result<FileLock, String> {
+        let path = format!("{}.lock", file_name);
+        let start = SystemTime::now();
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut f) => {
+                    let _ = write!(f, "{}", std::process::id());
+                    return Ok(FileLock { path });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(format!("{}: {}", path, e)),
+            }
+            // the lock of an instance which crashed is never released
+            let stale = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > LOCK_STALE);
+            if stale {
+                let _ = fs::remove_file(&path);
+            } else if start.elapsed().map_or(true, |waited| waited > LOCK_TIMEOUT) {
+                return Err(format!("{} is used by another instance", file_name));
+            } else {
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Uses the given directory for the data files instead of the default one.
+pub fn set_data_dir(dir: &str) {
+    let _ = DATA_DIR.set(PathBuf::from(dir));
+}
+
+/// The directory of the application under the XDG directory given by `xdg_var`, under `home_dir`
+/// in the home directory without it, or under %APPDATA%, the current directory as a last resort.
+fn user_dir(xdg_var: &str, home_dir: &str) -> PathBuf {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+
+    if let Some(dir) = non_empty(xdg_var) {
+        Path::new(&dir).join(APP_DIR)
+    } else if let Some(home) = non_empty("HOME") {
+        Path::new(&home).join(home_dir).join(APP_DIR)
+    } else if let Some(dir) = non_empty("APPDATA") {
+        Path::new(&dir).join(APP_DIR)
+    } else {
+        PathBuf::from(".")
+    }
+}
+
+/// The directory of the data files: $XDG_DATA_HOME/tetris, ~/.local/share/tetris or
+/// %APPDATA%\tetris, the current directory as a last resort.
+fn data_dir() -> &'static Path {
+    DATA_DIR.get_or_init(|| user_dir("XDG_DATA_HOME", ".local/share"))
+}
+
+/// Path of a data file, its directory being created if needed.
+fn data_file(name: &str) -> String {
+    let _ = fs::create_dir_all(data_dir());
+    data_dir().join(name).to_string_lossy().into_owned()
+}
+
+/// Uses the given directory for the settings instead of the default one.
+pub fn set_config_dir(dir: &str) {
+    let _ = CONFIG_DIR.set(PathBuf::from(dir));
+}
+
+/// The directory of the settings: $XDG_CONFIG_HOME/tetris, ~/.config/tetris or %APPDATA%\tetris,
+/// the current directory as a last resort.
+fn config_dir() -> &'static Path {
+    CONFIG_DIR.get_or_init(|| user_dir("XDG_CONFIG_HOME", ".config"))
+}
+
+/// Path of a settings file, its directory being created if needed. A file left in the data
+/// directory by an older version is moved there.
+fn config_file(name: &str) -> String {
+    let _ = fs::create_dir_all(config_dir());
+    let path = config_dir().join(name);
+    let old_path = data_dir().join(name);
+    if !path.exists() && old_path.exists() {
+        let _ = fs::rename(&old_path, &path);
+    }
+    path.to_string_lossy().into_owned()
+}
+
+/// Moves a corrupt file aside so it isn't overwritten, and returns its new path.
+fn set_aside(file_name: &str) -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let corrupt_name = format!("{}.corrupt-{}", file_name, seconds);
+    let _ = fs::rename(file_name, &corrupt_name);
+    corrupt_name
+}
+
+fn load_ranking<T: FromStr>(file_name: &str) -> Option<Vec<T>> {
+    read_from_file(file_name).ok()
+        .map(|content| line_to_slice(content.lines().next().unwrap_or("")))
+}
+
+pub fn save_zen_session(tetris: &Tetris) -> bool {
+    match serde_json::to_string(tetris) {
+        Ok(content) => write_into_file(&content, &data_file(ZEN_SESSION_FILE)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Loads the suspended Zen session, if any. The session file is removed once loaded so it's only
+/// resumed once, a session which can't be loaded is set aside.
+pub fn load_zen_session() -> Option<Tetris> {
+    let file_name = data_file(ZEN_SESSION_FILE);
+    let content = read_from_file(&file_name).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(tetris) => {
+            let _ = fs::remove_file(&file_name);
+            Some(tetris)
+        }
+        Err(e) => {
+            eprintln!("Warning: {} can't be resumed ({}), it was moved to {}", file_name, e, set_aside(&file_name));
+            None
+        }
+    }
+}
+
+/// Loads a piece set from a file, `name` is either the path of a JSON file or the name of one of
+/// the piece sets in assets/pieces.
+pub fn load_piece_set(name: &str) -> Result<PieceSet, String> {
+    let file_name = if name.ends_with(".json") {
+        name.to_owned()
+    } else {
+        format!("assets/pieces/{}.json", name)
+    };
+    let content = read_from_file(&file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+
+    PieceSet::from_json(&content).map_err(|e| format!("{}: {}", file_name, e))
+}
+
+/// Loads a puzzle pack from a file, `name` is either the path of a JSON file or the name of one of
+/// the packs in assets/puzzles.
+pub fn load_puzzle_pack(name: &str) -> Result<PuzzlePack, String> {
+    let file_name = if name.ends_with(".json") {
+        name.to_owned()
+    } else {
+        format!("{}/{}.json", PUZZLES_DIR, name)
+    };
+    let content = read_from_file(&file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    let stem = std::path::Path::new(&file_name).file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+    PuzzlePack::from_json(&content, &stem).map_err(|e| format!("{}: {}", file_name, e))
+}
+
+/// Loads every valid puzzle pack of assets/puzzles, sorted by file name.
+pub fn load_puzzle_packs() -> Vec<PuzzlePack> {
+    let mut file_names = match fs::read_dir(PUZZLES_DIR) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().to_string_lossy().into_owned())
+            .filter(|file_name| file_name.ends_with(".json"))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    file_names.sort();
+    file_names.iter().filter_map(|file_name| load_puzzle_pack(file_name).ok()).collect()
+}
+
+pub fn load_solved_puzzles() -> Vec<String> {
+    read_from_file(&data_file(SOLVED_PUZZLES_FILE))
+        .map(|content| content.lines().map(|line| line.to_owned()).collect())
+        .unwrap_or_default()
+}
+
+pub fn save_solved_puzzle(id: &str) -> bool {
+    let file_name = data_file(SOLVED_PUZZLES_FILE);
+    let _lock = match FileLock::lock(&file_name) {
+        Ok(lock) => lock,
+        Err(_) => return false,
+    };
+    let mut solved = load_solved_puzzles();
+
+    if solved.iter().any(|solved_id| solved_id == id) {
+        return true;
+    }
+    solved.push(id.to_owned());
+    write_into_file(&(solved.join("\n") + "\n"), &file_name).is_ok()
+}
+
+/// Saves the game in progress, to resume it later.
+pub fn save_game(tetris: &Tetris) -> bool {
+    let saved = SavedGame { version: SAVE_VERSION, game: tetris };
+    match serde_json::to_string(&saved) {
+        Ok(content) => write_into_file(&content, &data_file(SAVE_FILE)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn has_saved_game() -> bool {
+    Path::new(&data_file(SAVE_FILE)).is_file()
+}
+
+/// Loads the saved game. The save file is removed once loaded so the game is only resumed once,
+/// a save which can't be loaded is set aside.
+pub fn load_saved_game() -> Result<Tetris, String> {
+    let file_name = data_file(SAVE_FILE);
+    let content = read_from_file(&file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    // the version is checked before the game, whose format depends on it
+    let game = serde_json::from_str::<SavedGame<serde_json::Value>>(&content)
+        .map_err(|e| e.to_string())
+        .and_then(|saved| match saved.version {
+            SAVE_VERSION => serde_json::from_value::<Tetris>(saved.game).map_err(|e| e.to_string()),
+            version => Err(format!("version {} isn't supported, expected version {}", version, SAVE_VERSION)),
+        });
+
+    match game {
+        Ok(game) => {
+            let _ = fs::remove_file(&file_name);
+            Ok(game)
+        }
+        Err(e) => Err(format!("{}: {}, it was moved to {}", file_name, e, set_aside(&file_name))),
+    }
+}
+
+/// Saves a replay in the replays directory, named after the time it's saved at. Returns the path
+/// of the replay file.
+pub fn save_replay(replay: &Replay) -> Option<String> {
+    let dir = data_file(REPLAYS_DIR);
+    fs::create_dir_all(&dir).ok()?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+    // a counter is added to the replays saved within the same millisecond
+    let mut path = Path::new(&dir).join(format!("{}.json", millis));
+    let mut counter = 1;
+    while path.exists() {
+        path = Path::new(&dir).join(format!("{}-{}.json", millis, counter));
+        counter += 1;
+    }
+    let file_name = path.to_string_lossy().into_owned();
+    let content = serde_json::to_string(replay).ok()?;
+
+    write_into_file(&content, &file_name).ok()?;
+    Some(file_name)
+}
+
+pub fn load_replay(file_name: &str) -> Result<Replay, String> {
+    let content = read_from_file(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("{}: {}", file_name, e))
+}
+
+fn save_leaderboards(leaderboards: &Leaderboards, file_name: &str) -> bool {
+    match serde_json::to_string_pretty(leaderboards) {
+        Ok(content) => write_into_file(&content, file_name).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Loads the leaderboards. The first time, they're made from the records of the old files. A
+/// corrupt file is set aside with a warning and the leaderboards start over.
+fn load_leaderboards(file_name: &str) -> Result<Leaderboards, String> {
+    let content = match read_from_file(file_name) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let leaderboards = migrate_old_records();
+            save_leaderboards(&leaderboards, file_name);
+            return Ok(leaderboards);
+        }
+        Err(e) => return Err(format!("{}: {}", file_name, e)),
+    };
+    let leaderboards: Leaderboards = match serde_json::from_str(&content) {
+        Ok(leaderboards) => leaderboards,
+        Err(e) => {
+            eprintln!("Warning: {} is corrupt ({}), it was moved to {}", file_name, e, set_aside(file_name));
+            return Ok(Leaderboards::default());
+        }
+    };
+
+    if leaderboards.version != LEADERBOARD_VERSION {
+        return Err(format!("{}: version {} isn't supported, expected version {}", file_name,
+                           leaderboards.version, LEADERBOARD_VERSION));
+    }
+    Ok(leaderboards)
+}
+
+/// Loads the leaderboards, updates them and saves them if `update` returns true, without another
+/// instance changing them in between.
+pub fn update_leaderboards<F>(update: F) -> Result<Leaderboards, String>
+    where F: FnOnce(&mut Leaderboards) -> bool {
+    let file_name = data_file(LEADERBOARD_FILE);
+    let _lock = FileLock::lock(&file_name)?;
+    let mut leaderboards = load_leaderboards(&file_name)?;
+
+    if update(&mut leaderboards) && !save_leaderboards(&leaderboards, &file_name) {
+        return Err(format!("{}: couldn't be saved", file_name));
+    }
+    Ok(leaderboards)
+}
+
+/// Reads the records of scores.txt for Marathon and of the sprint_*, ultra_* and dig_* files, or
+/// the leaderboards saved before they were moved to the data directory, all from the current
+/// directory. The line counts of scores.txt can't be matched with its scores, so only the scores
+/// are kept.
+fn migrate_old_records() -> Leaderboards {
+    if let Some(leaderboards) = read_from_file(LEADERBOARD_FILE).ok()
+        .and_then(|content| serde_json::from_str::<Leaderboards>(&content).ok())
+        .filter(|leaderboards| leaderboards.version == LEADERBOARD_VERSION) {
+        return leaderboards;
+    }
+    let mut leaderboards = Leaderboards::default();
+
+    if let Some((highscores, _)) = load_highscores_and_lines() {
+        let leaderboard = leaderboards.board_mut(&format!("marathon {}", MIGRATED_RULES));
+        for score in highscores {
+            leaderboard.insert(Entry::migrated(score, 0, 0), Metric::Score);
+        }
+    }
+
+    let file_names = match fs::read_dir(".") {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    for file_name in file_names.iter() {
+        let settings = match file_name.strip_suffix(".txt") {
+            Some(stem) => stem.split('_').collect::<Vec<_>>(),
+            None => continue,
+        };
+        let numbers = settings.iter().skip(1).filter_map(|number| number.parse::<u64>().ok()).collect::<Vec<_>>();
+
+        let (mode, metric, entries) = match (settings[0], numbers.as_slice()) {
+            ("sprint", &[target_lines]) => (format!("sprint-{}", target_lines), Metric::Time,
+                load_ranking::<u64>(file_name).unwrap_or_default().iter()
+                    .map(|time| Entry::migrated(0, target_lines as u32, *time)).collect::<Vec<_>>()),
+            ("ultra", &[seconds]) => (format!("ultra-{}", seconds), Metric::Score,
+                load_ranking::<u32>(file_name).unwrap_or_default().iter()
+                    .map(|score| Entry::migrated(*score, 0, seconds * 1000)).collect()),
+            ("dig", &[garbage_lines, messiness]) => (format!("dig-{}-{}", garbage_lines, messiness), Metric::Time,
+                load_ranking::<u64>(file_name).unwrap_or_default().iter()
+                    .map(|time| Entry::migrated(0, 0, *time)).collect()),
+            _ => continue,
+        };
+        let mut leaderboard = Leaderboard::default();
+        for entry in entries {
+            leaderboard.insert(entry, metric);
+        }
+        *leaderboards.board_mut(&format!("{} {}", mode, MIGRATED_RULES)) = leaderboard;
+    }
+    leaderboards
+}
+
+fn read_profiles(file_name: &str) -> Result<Profiles, String> {
+    let content = match read_from_file(file_name) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Profiles::default()),
+        Err(e) => return Err(format!("{}: {}", file_name, e)),
+    };
+    let profiles = match serde_json::from_str::<Profiles>(&content) {
+        Ok(profiles) if !profiles.profiles.is_empty() => profiles,
+        Ok(_) => return Ok(Profiles::default()),
+        Err(e) => {
+            eprintln!("Warning: {} is corrupt ({}), it was moved to {}", file_name, e, set_aside(file_name));
+            return Ok(Profiles::default());
+        }
+    };
+
+    if profiles.version != PROFILES_VERSION {
+        return Err(format!("{}: version {} isn't supported, expected version {}", file_name,
+                           profiles.version, PROFILES_VERSION));
+    }
+    Ok(profiles)
+}
+
+/// Loads the profiles, with a default one the first time.
+pub fn load_profiles() -> Result<Profiles, String> {
+    read_profiles(&config_file(PROFILES_FILE))
+}
+
+/// Loads the profiles, updates them and saves them, without another instance changing them in
+/// between.
+pub fn update_profiles<F>(update: F) -> Result<Profiles, String>
+    where F: FnOnce(&mut Profiles) {
+    let file_name = config_file(PROFILES_FILE);
+    let _lock = FileLock::lock(&file_name)?;
+    let mut profiles = read_profiles(&file_name)?;
+
+    update(&mut profiles);
+    let content = serde_json::to_string_pretty(&profiles).map_err(|e| format!("{}: {}", file_name, e))?;
+    write_into_file(&content, &file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    Ok(profiles)
+}
+
+/// Adds a game at the end of the history, one JSON object per line.
+pub fn append_history(record: &Record) -> bool {
+    let file_name = data_file(HISTORY_FILE);
+    let line = match serde_json::to_string(record) {
+        Ok(line) => line + "\n",
+        Err(_) => return false,
+    };
+    let _lock = match FileLock::lock(&file_name) {
+        Ok(lock) => lock,
+        Err(_) => return false,
+    };
+
+    OpenOptions::new().create(true).append(true).open(&file_name)
+        .and_then(|mut f| f.write_all(line.as_bytes())).is_ok()
+}
+
+/// Loads the games of the history, oldest first. The lines which can't be read, like the last one
+/// after a crash, are skipped with a warning.
+pub fn load_history() -> Vec<Record> {
+    let file_name = data_file(HISTORY_FILE);
+    let content = read_from_file(&file_name).unwrap_or_default();
+    let mut records = Vec::new();
+
+    for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("Warning: {}:{} skipped ({})", file_name, i + 1, e),
+        }
+    }
+    records
+}
+
+/// Exports the games to a CSV file in the data directory. Returns the path of the file.
+pub fn export_history_csv(records: &[&Record]) -> Result<String, String> {
+    let file_name = data_file(HISTORY_CSV_FILE);
+    let mut content = CSV_HEADER.to_owned() + "\n";
+
+    for record in records.iter() {
+        content.push_str(&record.to_csv());
+        content.push('\n');
+    }
+    write_into_file(&content, &file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+    Ok(file_name)
+}
+
+/// Loads the library of openers which can be practiced.
+pub fn load_openers() -> Result<Vec<Opener>, String> {
+    let content = read_from_file(OPENERS_FILE).map_err(|e| format!("{}: {}", OPENERS_FILE, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("{}: {}", OPENERS_FILE, e))
+}
@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+use crate::tetrimino::Tetrimino;
+
+fn dropped(game_map: &[Vec<u8>], piece: &Tetrimino) -> Tetrimino {
+    let mut piece = piece.clone();
+
+    while piece.test_position(game_map, piece.current_state as usize, piece.x, piece.y + piece.step) {
+        piece.y += piece.step;
+    }
+    piece
+}
+
+/// The positions reached with one key: a tap on either side, a shift to either wall with the DAS
+/// and a rotation.
+fn moves(game_map: &[Vec<u8>], piece: &Tetrimino) -> Vec<Tetrimino> {
+    let mut moves = Vec::new();
+    let step = piece.step as isize;
+
+    for direction in [-step, step] {
+        let fits = |piece: &Tetrimino| {
+            piece.test_position(game_map, piece.current_state as usize, piece.x + direction, piece.y)
+        };
+        if !fits(piece) {
+            continue;
+        }
+        let mut tapped = piece.clone();
+        tapped.x += direction;
+        let mut shifted = tapped.clone();
+        while fits(&shifted) {
+            shifted.x += direction;
+        }
+        moves.push(tapped);
+        moves.push(shifted);
+    }
+    let mut rotated = piece.clone();
+    rotated.rotate(game_map);
+    moves.push(rotated);
+    moves
+}
+
+/// Minimum number of keys to place a piece where `target` is from where it spawned, moving it at
+/// the top of the map then hard dropping it. Every tap, rotation or shift to a wall with the DAS
+/// counts as one key. Returns None if the placement can't be reached that way, like the spins and
+/// the tucks.
+pub fn min_keys(game_map: &[Vec<u8>], spawn: &Tetrimino, target: &Tetrimino) -> Option<u32> {
+    let goal = target.cells();
+    let mut seen = vec![(spawn.x, spawn.y, spawn.current_state)];
+    let mut queue = VecDeque::new();
+    queue.push_back((spawn.clone(), 0));
+
+    while let Some((piece, keys)) = queue.pop_front() {
+        if dropped(game_map, &piece).cells() == goal {
+            return Some(keys);
+        }
+        for next in moves(game_map, &piece) {
+            let position = (next.x, next.y, next.current_state);
+            if !seen.contains(&position) {
+                seen.push(position);
+                queue.push_back((next, keys + 1));
+            }
+        }
+    }
+    None
+}
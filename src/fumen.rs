@@ -0,0 +1,551 @@
+use crate::piece_set::PieceSet;
+use crate::tetrimino::Tetrimino;
+use crate::tetris_struct::{GARBAGE, MAP_HEIGHT};
+
+const VERSION: &str = "v115@";
+const ENCODE_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_TABLE: &[u8] =
+    b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+// number of values a character of a comment is packed with
+const COMMENT_CHAR_VALUES: u32 = COMMENT_TABLE.len() as u32 + 1;
+const MAX_COMMENT_LENGTH: usize = 4095;
+
+const FIELD_WIDTH: usize = 10;
+// the field is 23 lines high, plus a garbage line beneath it
+const FIELD_TOP: usize = 23;
+const FIELD_BLOCKS: usize = (FIELD_TOP + 1) * FIELD_WIDTH;
+// number of lines of the field above our game map
+const HIDDEN_LINES: usize = FIELD_TOP - MAP_HEIGHT;
+
+// values of the blocks of the field, 1 to 7 are the tetriminos, in this order
+const EMPTY: u8 = 0;
+const I: u8 = 1;
+const O: u8 = 3;
+const Z: u8 = 4;
+const S: u8 = 7;
+const GRAY: u8 = 8;
+
+// rotations, in the order of their value
+pub const REVERSE: u8 = 0;
+pub const RIGHT: u8 = 1;
+pub const SPAWN: u8 = 2;
+pub const LEFT: u8 = 3;
+
+/// A page of a fumen: the game map before the piece is placed, the piece and a comment.
+pub struct Page {
+    pub game_map: Vec<Vec<u8>>,
+    pub piece: Option<Tetrimino>,
+    pub comment: String,
+}
+
+/// Positions of the minos of a tetrimino around its center in a given rotation, y going up.
+fn blocks(piece_type: u8, rotation: u8) -> Vec<(isize, isize)> {
+    let spawn: [(isize, isize); 4] = match piece_type {
+        1 => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        2 => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        3 => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        4 => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+        5 => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        6 => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        _ => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+    };
+    spawn.iter().map(|&(x, y)| match rotation {
+        RIGHT => (y, -x),
+        REVERSE => (-x, -y),
+        LEFT => (-y, x),
+        _ => (x, y),
+    }).collect()
+}
+
+/// Offset from the position written in the fumen to the center of the piece, the original editor
+/// writing the same position for the rotations of a piece which fill the same cells.
+fn position_offset(piece_type: u8, rotation: u8) -> (isize, isize) {
+    match (piece_type, rotation) {
+        (O, LEFT) => (1, -1),
+        (O, REVERSE) | (I, REVERSE) => (1, 0),
+        (O, SPAWN) | (I, LEFT) | (S, SPAWN) | (Z, SPAWN) => (0, -1),
+        (S, RIGHT) => (-1, 0),
+        (Z, LEFT) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+/// Translates the cells so their bounding box starts at (0, 0) and sorts them, to compare shapes.
+fn normalize(cells: &[(isize, isize)]) -> Vec<(isize, isize)> {
+    let min_x = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+    let min_y = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+    let mut normalized = cells.iter().map(|&(x, y)| (x - min_x, y - min_y)).collect::<Vec<_>>();
+    normalized.sort();
+    normalized
+}
+
+/// Cells of a state of a piece, y going up.
+fn state_cells(state: &[Vec<u8>]) -> Vec<(isize, isize)> {
+    state.iter().enumerate()
+        .flat_map(|(y, line)| line.iter().enumerate()
+            .filter(|(_, case)| **case != 0)
+            .map(move |(x, _)| (x as isize, -(y as isize))))
+        .collect()
+}
+
+/// The fumen value of every piece of the set, found from their shape. The pieces which aren't
+/// tetriminos are gray.
+pub fn piece_types(piece_set: &PieceSet) -> Vec<u8> {
+    piece_set.pieces.iter().map(|piece| {
+        let state = piece.states[0].iter()
+            .map(|line| line.chars().map(|c| if c == '#' { 1 } else { 0 }).collect())
+            .collect::<Vec<Vec<u8>>>();
+        let shape = normalize(&state_cells(&state));
+        (1..GRAY).find(|&piece_type| (0..4).any(|rotation| normalize(&blocks(piece_type, rotation)) == shape))
+            .unwrap_or(GRAY)
+    }).collect()
+}
+
+struct Values {
+    values: Vec<u32>,
+    position: usize,
+}
+
+impl Values {
+    fn poll(&mut self, nb_chars: usize) -> Result<u32, String> {
+        if self.position + nb_chars > self.values.len() {
+            return Err("the fumen is truncated".to_owned());
+        }
+        let value = self.values[self.position..self.position + nb_chars].iter().rev()
+            .fold(0, |value, digit| value * 64 + digit);
+        self.position += nb_chars;
+        Ok(value)
+    }
+
+    fn push(&mut self, mut value: u32, nb_chars: usize) {
+        for _ in 0..nb_chars {
+            self.values.push(value % 64);
+            value /= 64;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.values.len()
+    }
+}
+
+/// Escapes a comment like the escape function of JavaScript.
+fn escape(comment: &str) -> String {
+    let mut escaped = String::new();
+
+    for unit in comment.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some(c) if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) => escaped.push(c),
+            _ if unit < 256 => escaped += &format!("%{:02X}", unit),
+            _ => escaped += &format!("%u{:04X}", unit),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> String {
+    let chars = escaped.chars().collect::<Vec<_>>();
+    let mut units = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let hex = |start: usize, length: usize| chars.get(start..start + length)
+            .and_then(|digits| u16::from_str_radix(&digits.iter().collect::<String>(), 16).ok());
+        let unit = match chars[i] {
+            '%' if chars.get(i + 1) == Some(&'u') => hex(i + 2, 4).map(|unit| (unit, 6)),
+            '%' => hex(i + 1, 2).map(|unit| (unit, 3)),
+            _ => None,
+        };
+        match unit {
+            Some((unit, length)) => {
+                units.push(unit);
+                i += length;
+            }
+            None => {
+                let mut buffer = [0; 2];
+                units.extend_from_slice(chars[i].encode_utf16(&mut buffer));
+                i += 1;
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+/// Converts a game map to the blocks of a field, from the top line.
+fn to_field(game_map: &[Vec<u8>], types: &[u8]) -> Result<Vec<u8>, String> {
+    if game_map.len() != MAP_HEIGHT || game_map.iter().any(|line| line.len() != FIELD_WIDTH) {
+        return Err(format!("only {}x{} maps can be exported", FIELD_WIDTH, MAP_HEIGHT));
+    }
+    let mut field = vec![EMPTY; HIDDEN_LINES * FIELD_WIDTH];
+
+    for line in game_map.iter() {
+        field.extend(line.iter().map(|&case| match case {
+            0 => EMPTY,
+            GARBAGE => GRAY,
+            case => types.get(case as usize - 1).cloned().unwrap_or(GRAY),
+        }));
+    }
+    // the garbage line
+    field.extend([EMPTY; FIELD_WIDTH].iter());
+    Ok(field)
+}
+
+fn to_game_map(field: &[u8], types: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if field[..HIDDEN_LINES * FIELD_WIDTH].iter().any(|block| *block != EMPTY) {
+        return Err(format!("the board is higher than {} lines", MAP_HEIGHT));
+    }
+
+    Ok(field[HIDDEN_LINES * FIELD_WIDTH..FIELD_TOP * FIELD_WIDTH].chunks(FIELD_WIDTH)
+        .map(|line| line.iter().map(|&block| match block {
+            EMPTY => 0,
+            block => types.iter().position(|piece_type| *piece_type == block && block != GRAY)
+                .map(|index| index as u8 + 1)
+                .unwrap_or(GARBAGE),
+        }).collect())
+        .collect())
+}
+
+/// Returns the value of the piece, its rotation and the position of its center, y going up from
+/// the bottom line of the map.
+pub fn encode_piece(piece: &Tetrimino, types: &[u8]) -> Result<(u8, u8, isize, isize), String> {
+    let state = &piece.states[piece.current_state as usize];
+    let case = state.iter().flatten().find(|case| **case != 0).cloned().unwrap_or(0);
+    let piece_type = match types.get((case as usize).wrapping_sub(1)) {
+        Some(&piece_type) if piece.step == 1 && piece_type != GRAY => piece_type,
+        _ => return Err(format!("piece {} can't be exported", piece.name)),
+    };
+
+    let cells = state_cells(state).iter()
+        .map(|&(x, y)| (piece.x + x, (MAP_HEIGHT - 1 - piece.y) as isize + y))
+        .collect::<Vec<_>>();
+    let shape = normalize(&cells);
+    for &rotation in [SPAWN, RIGHT, REVERSE, LEFT].iter() {
+        let blocks = blocks(piece_type, rotation);
+        if normalize(&blocks) == shape {
+            // the center is where the bounding boxes of the piece and its blocks start
+            let x = cells.iter().map(|&(x, _)| x).min().unwrap() - blocks.iter().map(|&(x, _)| x).min().unwrap();
+            let y = cells.iter().map(|&(_, y)| y).min().unwrap() - blocks.iter().map(|&(_, y)| y).min().unwrap();
+            return Ok((piece_type, rotation, x, y));
+        }
+    }
+    Err(format!("piece {} can't be exported", piece.name))
+}
+
+/// The piece of the set with the value, in the rotation and with its center at the position, y
+/// going up from the bottom line of the map.
+pub fn decode_piece(piece_type: u8, rotation: u8, x: isize, y: isize, piece_set: &PieceSet,
+                types: &[u8]) -> Result<Tetrimino, String> {
+    let index = types.iter().position(|t| *t == piece_type)
+        .ok_or_else(|| format!("the {} piece set has no piece of type {}", piece_set.name, piece_type))?;
+    let mut piece = piece_set.create(index);
+    let cells = blocks(piece_type, rotation).iter()
+        .map(|&(dx, dy)| (x + dx, MAP_HEIGHT as isize - 1 - (y + dy)))
+        .collect::<Vec<_>>();
+    let (min_x, min_y) = (cells.iter().map(|&(x, _)| x).min().unwrap(), cells.iter().map(|&(_, y)| y).min().unwrap());
+    // our cells go down, the shape has to be compared upside down
+    let shape = normalize(&cells.iter().map(|&(x, y)| (x, -y)).collect::<Vec<_>>());
+
+    for (state_nb, state) in piece.states.iter().enumerate() {
+        let state_cells = state_cells(state);
+        if normalize(&state_cells) == shape {
+            let state_x = state_cells.iter().map(|&(x, _)| x).min().unwrap();
+            let state_y = state_cells.iter().map(|&(_, y)| -y).min().unwrap();
+            if min_y < state_y {
+                return Err(format!("piece {} is above the map", piece.name));
+            }
+            piece.current_state = state_nb as u8;
+            piece.x = min_x - state_x;
+            piece.y = (min_y - state_y) as usize;
+            return Ok(piece);
+        }
+    }
+    Err(format!("piece {} has no state matching the fumen", piece.name))
+}
+
+/// Puts the blocks of the piece in the field, then clears the complete lines.
+fn lock_piece(field: &mut Vec<u8>, piece: Option<(u8, u8, isize, isize)>) {
+    if let Some((piece_type, rotation, x, y)) = piece {
+        for (dx, dy) in blocks(piece_type, rotation) {
+            let line = FIELD_TOP as isize - 1 - (y + dy);
+            if (0..FIELD_TOP as isize + 1).contains(&line) && (0..FIELD_WIDTH as isize).contains(&(x + dx)) {
+                field[line as usize * FIELD_WIDTH + (x + dx) as usize] = piece_type;
+            }
+        }
+    }
+    let mut lines = field[..FIELD_TOP * FIELD_WIDTH].chunks(FIELD_WIDTH)
+        .filter(|line| line.contains(&EMPTY))
+        .map(|line| line.to_vec())
+        .collect::<Vec<_>>();
+    while lines.len() < FIELD_TOP {
+        lines.insert(0, vec![EMPTY; FIELD_WIDTH]);
+    }
+    let garbage_line = field[FIELD_TOP * FIELD_WIDTH..].to_vec();
+    *field = lines.concat();
+    field.extend(garbage_line);
+}
+
+/// Decodes every page of a fumen, which can also be given as an URL.
+pub fn decode(fumen: &str, piece_set: &PieceSet) -> Result<Vec<Page>, String> {
+    let data = match fumen.find(VERSION) {
+        Some(start) => &fumen[start + VERSION.len()..],
+        None => return Err("only v115 fumens are supported".to_owned()),
+    };
+    let mut values = Values {
+        values: data.bytes().filter(|c| *c != b'?')
+            .map(|c| ENCODE_TABLE.iter().position(|e| *e == c).map(|value| value as u32)
+                .ok_or_else(|| format!("invalid character {} in the fumen", c as char)))
+            .collect::<Result<_, _>>()?,
+        position: 0,
+    };
+    let types = piece_types(piece_set);
+    let mut pages = Vec::new();
+    let mut field = vec![EMPTY; FIELD_BLOCKS];
+    let mut comment = String::new();
+    let mut repeat = 0;
+
+    while !values.is_empty() {
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut index = 0;
+            let mut changed = true;
+            while index < FIELD_BLOCKS {
+                let value = values.poll(2)?;
+                let (diff, nb_blocks) = ((value / FIELD_BLOCKS as u32) as i32, (value % FIELD_BLOCKS as u32) as usize + 1);
+                if diff == 8 && nb_blocks == FIELD_BLOCKS {
+                    changed = false;
+                }
+                for block in field.iter_mut().skip(index).take(nb_blocks) {
+                    *block = (*block as i32 + diff - 8).clamp(0, GRAY as i32) as u8;
+                }
+                index += nb_blocks;
+            }
+            if !changed {
+                repeat = values.poll(1)?;
+            }
+        }
+
+        let mut action = values.poll(3)?;
+        let piece_type = (action % 8) as u8;
+        action /= 8;
+        let rotation = (action % 4) as u8;
+        action /= 4;
+        let position = action % FIELD_BLOCKS as u32;
+        action /= FIELD_BLOCKS as u32;
+        let rise = action % 2 == 1;
+        let mirror = action / 2 % 2 == 1;
+        let has_comment = action / 8 % 2 == 1;
+        let lock = action / 16 % 2 == 0;
+
+        if has_comment {
+            let length = values.poll(2)? as usize;
+            let mut escaped = String::new();
+            for _ in 0..length.div_ceil(4) {
+                let mut value = values.poll(5)?;
+                for _ in 0..4 {
+                    escaped.push(COMMENT_TABLE[(value % COMMENT_CHAR_VALUES) as usize % COMMENT_TABLE.len()] as char);
+                    value /= COMMENT_CHAR_VALUES;
+                }
+            }
+            escaped.truncate(length);
+            comment = unescape(&escaped);
+        }
+
+        let piece = if piece_type != EMPTY && piece_type < GRAY {
+            let (offset_x, offset_y) = position_offset(piece_type, rotation);
+            let x = (position as usize % FIELD_WIDTH) as isize + offset_x;
+            let y = (FIELD_TOP - 1 - position as usize / FIELD_WIDTH) as isize + offset_y;
+            Some((piece_type, rotation, x, y))
+        } else {
+            None
+        };
+        pages.push(Page {
+            game_map: to_game_map(&field, &types)?,
+            piece: match piece {
+                Some((piece_type, rotation, x, y)) => Some(decode_piece(piece_type, rotation, x, y, piece_set, &types)?),
+                None => None,
+            },
+            comment: comment.clone(),
+        });
+
+        if lock {
+            lock_piece(&mut field, piece);
+            if rise {
+                // the garbage line goes up into the field
+                field.drain(..FIELD_WIDTH);
+                field.extend([EMPTY; FIELD_WIDTH].iter());
+            }
+            if mirror {
+                for line in field[..FIELD_TOP * FIELD_WIDTH].chunks_mut(FIELD_WIDTH) {
+                    line.reverse();
+                }
+            }
+        }
+    }
+
+    if pages.is_empty() {
+        return Err("the fumen has no page".to_owned());
+    }
+    Ok(pages)
+}
+
+/// Encodes the pages in a fumen, every piece being locked before the next page.
+pub fn encode(pages: &[Page], piece_set: &PieceSet) -> Result<String, String> {
+    let types = piece_types(piece_set);
+    let mut values = Values { values: Vec::new(), position: 0 };
+    let mut previous_field = vec![EMPTY; FIELD_BLOCKS];
+    let mut previous_comment = String::new();
+    // index of the number of following pages which have the same field
+    let mut repeat_index: Option<usize> = None;
+
+    for (page_nb, page) in pages.iter().enumerate() {
+        let mut field = to_field(&page.game_map, &types)?;
+        let mut runs: Vec<(u32, usize)> = Vec::new();
+        for (block, previous) in field.iter().zip(previous_field.iter()) {
+            let diff = *block as u32 + 8 - *previous as u32;
+            match runs.last_mut() {
+                Some((last_diff, count)) if *last_diff == diff => *count += 1,
+                _ => runs.push((diff, 1)),
+            }
+        }
+
+        let unchanged = runs.len() == 1 && runs[0].0 == 8;
+        match repeat_index {
+            Some(index) if unchanged && values.values[index] < 63 => values.values[index] += 1,
+            _ => {
+                for (diff, count) in runs {
+                    values.push(diff * FIELD_BLOCKS as u32 + count as u32 - 1, 2);
+                }
+                repeat_index = if unchanged {
+                    values.push(0, 1);
+                    Some(values.values.len() - 1)
+                } else {
+                    None
+                };
+            }
+        }
+
+        let piece = match page.piece {
+            Some(ref piece) => Some(encode_piece(piece, &types)?),
+            None => None,
+        };
+        let (piece_type, rotation, position) = match piece {
+            Some((piece_type, rotation, x, y)) => {
+                let (offset_x, offset_y) = position_offset(piece_type, rotation);
+                let (x, y) = (x - offset_x, y - offset_y);
+                (piece_type, rotation, (FIELD_TOP as isize - y - 1) * FIELD_WIDTH as isize + x)
+            }
+            None => (EMPTY, REVERSE, 0),
+        };
+        let has_comment = page.comment != previous_comment;
+        let colorize = page_nb == 0;
+
+        // lock, comment, colorize, mirror and rise flags
+        let mut action = (has_comment as u32) * 2 + colorize as u32;
+        action *= 4;
+        action = action * FIELD_BLOCKS as u32 + position as u32;
+        action = (action * 4 + rotation as u32) * 8 + piece_type as u32;
+        values.push(action, 3);
+
+        if has_comment {
+            let escaped = escape(&page.comment);
+            if escaped.len() > MAX_COMMENT_LENGTH {
+                return Err("the comment is too long".to_owned());
+            }
+            values.push(escaped.len() as u32, 2);
+            for chunk in escaped.as_bytes().chunks(4) {
+                let value = chunk.iter().rev().fold(0, |value, c| {
+                    value * COMMENT_CHAR_VALUES + COMMENT_TABLE.iter().position(|e| e == c).unwrap_or(0) as u32
+                });
+                values.push(value, 5);
+            }
+            previous_comment = page.comment.clone();
+        }
+
+        lock_piece(&mut field, piece);
+        previous_field = field;
+    }
+
+    let data = values.values.iter().map(|value| ENCODE_TABLE[*value as usize] as char).collect::<String>();
+    // a ? is put every 47 characters after the first 42 ones, as in the original editor
+    let mut chunks = vec![data.chars().take(42).collect::<String>()];
+    let rest = data.chars().skip(42).collect::<Vec<_>>();
+    chunks.extend(rest.chunks(47).map(|chunk| chunk.iter().collect::<String>()));
+    Ok(format!("{}{}", VERSION, chunks.join("?")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris_struct::MAP_WIDTH;
+
+    // a piece lying on the floor with its leftmost mino in the 4th column, in every rotation
+    // clockwise from the spawn one, as written by the original editor
+    const PIECES: [(&str, [(usize, usize); 4]); 28] = [
+        ("v115@vhARQJ", [(3, 15), (4, 15), (5, 15), (6, 15)]),
+        ("v115@vhApFJ", [(3, 12), (3, 13), (3, 14), (3, 15)]),
+        ("v115@vhABQJ", [(3, 15), (4, 15), (5, 15), (6, 15)]),
+        ("v115@vhA5FJ", [(3, 12), (3, 13), (3, 14), (3, 15)]),
+        ("v115@vhASQJ", [(3, 15), (4, 15), (5, 14), (5, 15)]),
+        ("v115@vhAqKJ", [(3, 13), (3, 14), (3, 15), (4, 15)]),
+        ("v115@vhACLJ", [(3, 14), (3, 15), (4, 14), (5, 14)]),
+        ("v115@vhAaLJ", [(3, 13), (4, 13), (4, 14), (4, 15)]),
+        ("v115@vhAzKJ", [(3, 14), (3, 15), (4, 14), (4, 15)]),
+        ("v115@vhArKJ", [(3, 14), (3, 15), (4, 14), (4, 15)]),
+        ("v115@vhAjKJ", [(3, 14), (3, 15), (4, 14), (4, 15)]),
+        ("v115@vhA7KJ", [(3, 14), (3, 15), (4, 14), (4, 15)]),
+        ("v115@vhAULJ", [(3, 14), (4, 14), (4, 15), (5, 15)]),
+        ("v115@vhAsKJ", [(3, 14), (3, 15), (4, 13), (4, 14)]),
+        ("v115@vhAELJ", [(3, 14), (4, 14), (4, 15), (5, 15)]),
+        ("v115@vhA8KJ", [(3, 14), (3, 15), (4, 13), (4, 14)]),
+        ("v115@vhAVQJ", [(3, 15), (4, 14), (4, 15), (5, 15)]),
+        ("v115@vhAtKJ", [(3, 13), (3, 14), (3, 15), (4, 14)]),
+        ("v115@vhAFLJ", [(3, 14), (4, 14), (4, 15), (5, 14)]),
+        ("v115@vhAdLJ", [(3, 14), (4, 13), (4, 14), (4, 15)]),
+        ("v115@vhAWQJ", [(3, 14), (3, 15), (4, 15), (5, 15)]),
+        ("v115@vhAuKJ", [(3, 13), (3, 14), (3, 15), (4, 13)]),
+        ("v115@vhAGLJ", [(3, 14), (4, 14), (5, 14), (5, 15)]),
+        ("v115@vhAeLJ", [(3, 15), (4, 13), (4, 14), (4, 15)]),
+        ("v115@vhAXLJ", [(3, 15), (4, 14), (4, 15), (5, 14)]),
+        ("v115@vhAPLJ", [(3, 13), (3, 14), (4, 14), (4, 15)]),
+        ("v115@vhAHLJ", [(3, 15), (4, 14), (4, 15), (5, 14)]),
+        ("v115@vhAfLJ", [(3, 13), (3, 14), (4, 14), (4, 15)]),
+    ];
+
+    fn decode_piece_cells(fumen: &str) -> Vec<(usize, usize)> {
+        let pages = decode(fumen, &PieceSet::standard()).unwrap();
+        pages[0].piece.as_ref().unwrap().cells()
+    }
+
+    #[test]
+    fn pieces_are_where_the_editor_puts_them() {
+        for (fumen, piece_cells) in PIECES.iter() {
+            assert_eq!(decode_piece_cells(fumen), piece_cells, "{}", fumen);
+        }
+    }
+
+    #[test]
+    fn pieces_are_encoded_as_the_editor_does() {
+        let piece_set = PieceSet::standard();
+        for (fumen, piece_cells) in PIECES.iter() {
+            let pages = decode(fumen, &piece_set).unwrap();
+            let encoded = encode(&pages, &piece_set).unwrap();
+            // the rotations filling the same cells are written as the first one of them
+            let first = PIECES.iter().find(|(_, other)| other == piece_cells).unwrap().0;
+            assert_eq!(encoded, first, "{}", fumen);
+            assert_eq!(decode_piece_cells(&encoded), piece_cells);
+        }
+    }
+
+    #[test]
+    fn an_o_on_the_floor_is_written_at_its_top_left_mino() {
+        let piece_set = PieceSet::standard();
+        let mut piece = piece_set.create(piece_set.position('O').unwrap());
+        piece.x = 0;
+        piece.y = MAP_HEIGHT - 2;
+        let game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT];
+        let page = Page { game_map, piece: Some(piece), comment: String::new() };
+        // the position 210 is the first column of the second line of the field
+        assert_eq!(encode(&[page], &piece_set).unwrap(), "v115@vhATJJ");
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Serialize, Deserialize};
+
+use crate::master::Master;
+use crate::practice::Practice;
+use crate::puzzle::Puzzle;
+use crate::tetris_struct::{Tetris, TICKS_PER_SECOND};
+
+pub const SPRINT_DEFAULT_LINES: u32 = 40;
+pub const ULTRA_DEFAULT_SECONDS: u64 = 120;
+pub const DIG_DEFAULT_LINES: u32 = 10;
+pub const DIG_DEFAULT_MESSINESS: u32 = 30;
+// garbage lines a dig can start with, the stack staying below the spawn of the pieces
+const DIG_MAX_LINES: u32 = 12;
+const SPRINT_SPLIT_LINES: u32 = 10;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum GameMode {
+    Marathon,
+    Sprint {
+        target_lines: u32,
+        // time in milliseconds at every SPRINT_SPLIT_LINES lines
+        splits: Vec<u64>,
+    },
+    Ultra {
+        time_limit: u64,
+    },
+    Dig {
+        garbage_lines: u32,
+        // percentage of chance for the hole to change column between two garbage lines
+        messiness: u32,
+    },
+    Zen,
+    Master(Master),
+    Puzzle(Puzzle),
+    Practice(Practice),
+    // against the AI, the attack of the line clears being sent as garbage
+    Versus {
+        // lines of garbage received from the opponent
+        received: u32,
+        // true once the opponent topped out
+        won: bool,
+    },
+}
+
+impl GameMode {
+    pub fn sprint(target_lines: u32) -> GameMode {
+        GameMode::Sprint {
+            target_lines,
+            splits: Vec::new(),
+        }
+    }
+
+    pub fn ultra(seconds: u64) -> GameMode {
+        GameMode::Ultra {
+            time_limit: seconds * 1000,
+        }
+    }
+
+    pub fn versus() -> GameMode {
+        GameMode::Versus {
+            received: 0,
+            won: false,
+        }
+    }
+
+    pub fn dig(garbage_lines: u32, messiness: u32) -> Result<GameMode, String> {
+        if garbage_lines == 0 || garbage_lines > DIG_MAX_LINES {
+            return Err(format!("{} garbage lines, a dig has 1 to {}", garbage_lines, DIG_MAX_LINES));
+        }
+        if messiness > 100 {
+            return Err(format!("{}% messiness, it's at most 100%", messiness));
+        }
+        Ok(GameMode::Dig { garbage_lines, messiness })
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            GameMode::Marathon => "Marathon".to_owned(),
+            GameMode::Sprint { target_lines, .. } => format!("Sprint {}L", target_lines),
+            GameMode::Ultra { time_limit } => format!("Ultra {}", format_time(time_limit)),
+            GameMode::Dig { garbage_lines, messiness } =>
+                format!("Dig {}L ({}% messiness)", garbage_lines, messiness),
+            GameMode::Zen => "Zen".to_owned(),
+            GameMode::Master(_) => "Master".to_owned(),
+            GameMode::Puzzle(ref puzzle) => format!("Puzzle {}", puzzle.name),
+            GameMode::Practice(ref practice) => format!("Practice {}", practice.opener),
+            GameMode::Versus { .. } => "Versus AI".to_owned(),
+        }
+    }
+
+    /// Whether the results of the mode are ranked.
+    pub fn is_ranked(&self) -> bool {
+        match *self {
+            GameMode::Marathon | GameMode::Sprint { .. } | GameMode::Ultra { .. } | GameMode::Dig { .. } |
+            GameMode::Master(_) => true,
+            GameMode::Zen | GameMode::Puzzle(_) | GameMode::Practice(_) | GameMode::Versus { .. } => false,
+        }
+    }
+
+    /// Whether the placements can be taken back, only when playing alone in a mode which isn't
+    /// ranked.
+    pub fn can_undo(&self) -> bool {
+        !self.is_ranked() && !matches!(*self, GameMode::Versus { .. })
+    }
+
+    /// Short name of the mode and its settings for the leaderboards, None if it isn't ranked.
+    pub fn key(&self) -> Option<String> {
+        match *self {
+            GameMode::Marathon => Some("marathon".to_owned()),
+            GameMode::Sprint { target_lines, .. } => Some(format!("sprint-{}", target_lines)),
+            GameMode::Ultra { time_limit } => Some(format!("ultra-{}", time_limit / 1000)),
+            GameMode::Dig { garbage_lines, messiness } => Some(format!("dig-{}-{}", garbage_lines, messiness)),
+            GameMode::Master(_) => Some("master".to_owned()),
+            GameMode::Zen | GameMode::Puzzle(_) | GameMode::Practice(_) | GameMode::Versus { .. } => None,
+        }
+    }
+
+    pub fn on_lines_cleared(&mut self, nb_lines: u32, frame: u64) {
+        if let GameMode::Sprint { target_lines, ref mut splits } = *self {
+            let reached = nb_lines.min(target_lines) / SPRINT_SPLIT_LINES;
+
+            while (splits.len() as u32) < reached {
+                splits.push(frames_to_millis(frame));
+            }
+        }
+    }
+
+    pub fn is_complete(&self, tetris: &Tetris) -> bool {
+        match *self {
+            GameMode::Marathon | GameMode::Zen => false,
+            GameMode::Sprint { target_lines, .. } => tetris.nb_lines >= target_lines,
+            GameMode::Ultra { time_limit } => tetris.elapsed_millis() >= time_limit,
+            GameMode::Dig { .. } => tetris.garbage_left() == 0,
+            GameMode::Master(ref master) => master.is_complete(),
+            GameMode::Puzzle(ref puzzle) => puzzle.is_solved(),
+            GameMode::Practice(ref practice) => practice.is_complete(),
+            GameMode::Versus { won, .. } => won,
+        }
+    }
+}
+
+pub fn frames_to_millis(frames: u64) -> u64 {
+    frames * 1000 / TICKS_PER_SECOND
+}
+
+/// Number of ticks closest to the duration.
+pub fn millis_to_frames(millis: u64) -> u64 {
+    (millis * TICKS_PER_SECOND + 500) / 1000
+}
+
+pub fn format_time(millis: u64) -> String {
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
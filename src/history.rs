@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::game_mode::format_time;
+use crate::leaderboard::{Metric, format_date};
+use crate::tetris_struct::{ClearStats, Tetris};
+
+// number of weeks shown in the trends of a mode
+const TREND_WEEKS: usize = 6;
+const SECONDS_PER_DAY: u64 = 86400;
+
+pub const CSV_HEADER: &str = "date,profile,mode,rules,completed,duration_ms,score,lines,pieces,pps,apm,kpp,\
+                              finesse_faults,singles,doubles,triples,tetrises,t_spins,all_clears,attack";
+
+/// A finished game, as kept in the history.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Record {
+    // seconds since the Unix epoch
+    pub date: u64,
+    pub profile: String,
+    // name of the mode with its settings, and key of the rules
+    pub mode: String,
+    pub rules: String,
+    // what the mode is ranked by, None if it isn't
+    pub metric: Option<Metric>,
+    // false if the game ended before the goal of its mode
+    pub completed: bool,
+    // duration of the game in milliseconds
+    pub duration: u64,
+    pub score: u32,
+    pub lines: u32,
+    pub pieces: u32,
+    // pieces per second, attack per minute and keys per piece
+    pub pps: f32,
+    pub apm: f32,
+    pub kpp: f32,
+    // None for the games played before it was counted
+    pub finesse_faults: Option<u32>,
+    pub clears: ClearStats,
+}
+
+impl Record {
+    pub fn new(tetris: &Tetris, profile: &str) -> Record {
+        let duration = tetris.elapsed_millis();
+
+        Record {
+            date: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+            profile: profile.to_owned(),
+            mode: tetris.mode.name(),
+            rules: tetris.rules.key(),
+            metric: Metric::of(&tetris.mode),
+            completed: tetris.is_finished(),
+            duration,
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            pieces: tetris.nb_pieces,
+            pps: tetris.pps(),
+            apm: if duration > 0 { tetris.clear_stats.attack as f32 * 60_000.0 / duration as f32 } else { 0.0 },
+            kpp: tetris.kpp(),
+            finesse_faults: Some(tetris.finesse_faults),
+            clears: tetris.clear_stats.clone(),
+        }
+    }
+
+    /// The name of the mode, along with the rules when they aren't the default ones.
+    pub fn label(&self) -> String {
+        if self.rules == "standard" {
+            self.mode.clone()
+        } else {
+            format!("{} [{}]", self.mode, self.rules)
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let finesse_faults = self.finesse_faults.map(|faults| faults.to_string()).unwrap_or_default();
+
+        format!("{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{},{},{},{},{},{},{},{}",
+                format_date(self.date), csv_field(&self.profile), csv_field(&self.mode), csv_field(&self.rules),
+                self.completed, self.duration, self.score, self.lines, self.pieces, self.pps, self.apm,
+                self.kpp, finesse_faults, self.clears.singles, self.clears.doubles, self.clears.triples,
+                self.clears.tetrises, self.clears.t_spins, self.clears.all_clears, self.clears.attack)
+    }
+}
+
+/// Quotes the field if it has a comma, a quote or a line break in it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// The games of the history grouped by mode and rules, sorted by label.
+pub fn by_mode<'a>(records: &[&'a Record]) -> BTreeMap<String, Vec<&'a Record>> {
+    let mut modes: BTreeMap<String, Vec<&Record>> = BTreeMap::new();
+
+    for record in records.iter() {
+        modes.entry(record.label()).or_default().push(record);
+    }
+    modes
+}
+
+/// The best of the games by the metric of their mode, as text.
+fn best(records: &[&Record]) -> Option<String> {
+    let completed = records.iter().filter(|record| record.completed);
+
+    match records.first()?.metric? {
+        Metric::Score => records.iter().map(|record| record.score).max().map(|score| score.to_string()),
+        Metric::Time => completed.map(|record| record.duration).min().map(format_time),
+    }
+}
+
+fn averages(records: &[&Record]) -> String {
+    let count = records.len().max(1) as f32;
+    let average = |value: fn(&Record) -> f32| records.iter().map(|record| value(record)).sum::<f32>() / count;
+
+    format!("{:.2} PPS, {:.1} APM, {:.2} KPP", average(|record| record.pps), average(|record| record.apm),
+            average(|record| record.kpp))
+}
+
+/// Lines describing the games of a mode: totals, averages and how they went week after week, the
+/// latest week first.
+pub fn summary(records: &[&Record]) -> Vec<String> {
+    let completed = records.iter().filter(|record| record.completed).count();
+    let play_time = records.iter().map(|record| record.duration).sum();
+    let mut lines = vec![
+        format!("Games: {} ({} completed)", records.len(), completed),
+        format!("Play time: {}", format_time(play_time)),
+        format!("Average: {}", averages(records)),
+    ];
+    if let Some(best) = best(records) {
+        lines.push(format!("Best: {}", best));
+    }
+
+    // by the Monday starting the week, 1970-01-01 being a Thursday
+    let mut weeks: BTreeMap<u64, Vec<&Record>> = BTreeMap::new();
+    for record in records.iter() {
+        let day = record.date / SECONDS_PER_DAY;
+        let monday = day.saturating_sub((day + 3) % 7);
+        weeks.entry(monday).or_default().push(record);
+    }
+    lines.push("Weekly:".to_owned());
+    for (monday, records) in weeks.iter().rev().take(TREND_WEEKS) {
+        lines.push(format!("  {}: {} games{}", format_date(monday * SECONDS_PER_DAY), records.len(),
+                           best(records).map(|best| format!(", best {}", best)).unwrap_or_default()));
+        lines.push(format!("    {}", averages(records)));
+    }
+    lines
+}
@@ -0,0 +1,259 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::game_mode::GameMode;
+use crate::tetris_struct::Tetris;
+
+// version of the leaderboard file format
+pub const LEADERBOARD_VERSION: u32 = 1;
+pub const LEADERBOARD_SIZE: usize = 5;
+
+/// A game in a leaderboard.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    // duration of the game in milliseconds
+    pub duration: u64,
+    // pieces per second
+    pub pps: f32,
+    // seconds since the Unix epoch, None for the records migrated from the old files
+    pub date: Option<u64>,
+    // path of the replay file of the game
+    pub replay: Option<String>,
+}
+
+impl Entry {
+    pub fn new(tetris: &Tetris, name: &str, replay: Option<String>) -> Entry {
+        let duration = tetris.elapsed_millis();
+        let level = match tetris.mode {
+            GameMode::Master(ref master) => master.level,
+            _ => tetris.current_level,
+        };
+
+        Entry {
+            name: name.to_owned(),
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            level,
+            duration,
+            pps: tetris.pps(),
+            date: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).ok(),
+            replay,
+        }
+    }
+
+    /// An entry of the old files, which only kept one value per game.
+    pub fn migrated(score: u32, lines: u32, duration: u64) -> Entry {
+        Entry {
+            name: "Unknown".to_owned(),
+            score,
+            lines,
+            level: 0,
+            duration,
+            pps: 0.0,
+            date: None,
+            replay: None,
+        }
+    }
+
+    pub fn format_date(&self) -> String {
+        match self.date {
+            Some(date) => format_date(date),
+            None => "unknown date".to_owned(),
+        }
+    }
+}
+
+/// The date of the given second since the Unix epoch as YYYY-MM-DD, in UTC.
+pub fn format_date(date: u64) -> String {
+    // civil date from the number of days since 1970-01-01, in eras of 400 years
+    let days = (date / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// What the games of a leaderboard are ranked by.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Metric {
+    // the higher the better, ties are broken by the shortest duration
+    Score,
+    // the lower the better, ties are broken by the highest score
+    Time,
+}
+
+impl Metric {
+    /// The metric of a ranked mode.
+    pub fn of(mode: &GameMode) -> Option<Metric> {
+        match *mode {
+            GameMode::Marathon | GameMode::Ultra { .. } | GameMode::Master(_) => Some(Metric::Score),
+            GameMode::Sprint { .. } | GameMode::Dig { .. } => Some(Metric::Time),
+            GameMode::Zen | GameMode::Puzzle(_) | GameMode::Practice(_) | GameMode::Versus { .. } => None,
+        }
+    }
+
+    /// Orders two games, the first one being the best.
+    pub fn compare(self, a: &Entry, b: &Entry) -> Ordering {
+        match self {
+            Metric::Score => b.score.cmp(&a.score).then(a.duration.cmp(&b.duration)),
+            Metric::Time => a.duration.cmp(&b.duration).then(b.score.cmp(&a.score)),
+        }
+    }
+}
+
+/// The best LEADERBOARD_SIZE games of a mode, from the best to the worst.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Leaderboard {
+    entries: Vec<Entry>,
+}
+
+impl Leaderboard {
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The rank the game would get, from 1. A game tied with others ranks after them.
+    pub fn rank(&self, entry: &Entry, metric: Metric) -> usize {
+        self.entries.iter().take_while(|old| metric.compare(entry, old) != Ordering::Less).count() + 1
+    }
+
+    /// Inserts the game at its rank. Returns the rank, or None if the game doesn't make it to the
+    /// leaderboard.
+    pub fn insert(&mut self, entry: Entry, metric: Metric) -> Option<usize> {
+        let rank = self.rank(&entry, metric);
+
+        if rank > LEADERBOARD_SIZE {
+            return None;
+        }
+        self.entries.insert(rank - 1, entry);
+        self.entries.truncate(LEADERBOARD_SIZE);
+        Some(rank)
+    }
+}
+
+/// The best games of every ranked mode, for every set of rules.
+#[derive(Serialize, Deserialize)]
+pub struct Leaderboards {
+    pub version: u32,
+    // the key is the key of the mode followed by the key of the rules
+    pub boards: BTreeMap<String, Leaderboard>,
+}
+
+impl Default for Leaderboards {
+    fn default() -> Leaderboards {
+        Leaderboards {
+            version: LEADERBOARD_VERSION,
+            boards: BTreeMap::new(),
+        }
+    }
+}
+
+impl Leaderboards {
+    /// The key of the leaderboard of the game, None if its mode isn't ranked.
+    pub fn key(tetris: &Tetris) -> Option<String> {
+        Some(format!("{} {}", tetris.mode.key()?, tetris.rules.key()))
+    }
+
+    pub fn board_mut(&mut self, key: &str) -> &mut Leaderboard {
+        self.boards.entry(key.to_owned()).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: u32, duration: u64, name: &str) -> Entry {
+        let mut entry = Entry::migrated(score, 0, duration);
+        entry.name = name.to_owned();
+        entry
+    }
+
+    fn names(leaderboard: &Leaderboard) -> Vec<&str> {
+        leaderboard.entries().iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn scores_are_ranked_from_the_highest() {
+        let mut leaderboard = Leaderboard::default();
+
+        assert_eq!(leaderboard.insert(entry(100, 0, "a"), Metric::Score), Some(1));
+        assert_eq!(leaderboard.insert(entry(300, 0, "b"), Metric::Score), Some(1));
+        assert_eq!(leaderboard.insert(entry(200, 0, "c"), Metric::Score), Some(2));
+        assert_eq!(names(&leaderboard), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn times_are_ranked_from_the_lowest() {
+        let mut leaderboard = Leaderboard::default();
+
+        assert_eq!(leaderboard.insert(entry(0, 60_000, "a"), Metric::Time), Some(1));
+        assert_eq!(leaderboard.insert(entry(0, 90_000, "b"), Metric::Time), Some(2));
+        assert_eq!(leaderboard.insert(entry(0, 45_000, "c"), Metric::Time), Some(1));
+        assert_eq!(names(&leaderboard), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn full_leaderboard_keeps_the_best_games() {
+        let mut leaderboard = Leaderboard::default();
+        for score in 1..=LEADERBOARD_SIZE as u32 {
+            leaderboard.insert(entry(score * 100, 0, &score.to_string()), Metric::Score);
+        }
+
+        assert_eq!(leaderboard.insert(entry(50, 0, "worst"), Metric::Score), None);
+        assert_eq!(leaderboard.insert(entry(250, 0, "middle"), Metric::Score), Some(4));
+        assert_eq!(leaderboard.entries().len(), LEADERBOARD_SIZE);
+        assert_eq!(names(&leaderboard), ["5", "4", "3", "middle", "2"]);
+    }
+
+    #[test]
+    fn ties_are_broken_by_the_other_metric_then_by_age() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.insert(entry(100, 60_000, "slow"), Metric::Score);
+
+        assert_eq!(leaderboard.insert(entry(100, 30_000, "fast"), Metric::Score), Some(1));
+        assert_eq!(leaderboard.insert(entry(100, 30_000, "later"), Metric::Score), Some(2));
+        assert_eq!(names(&leaderboard), ["fast", "later", "slow"]);
+
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.insert(entry(100, 60_000, "low"), Metric::Time);
+        assert_eq!(leaderboard.insert(entry(200, 60_000, "high"), Metric::Time), Some(1));
+    }
+
+    #[test]
+    fn rank_is_reported_without_inserting() {
+        let mut leaderboard = Leaderboard::default();
+        for score in [500, 400, 300] {
+            leaderboard.insert(entry(score, 0, ""), Metric::Score);
+        }
+
+        assert_eq!(leaderboard.rank(&entry(350, 0, ""), Metric::Score), 3);
+        assert_eq!(leaderboard.rank(&entry(100, 0, ""), Metric::Score), 4);
+        assert_eq!(leaderboard.entries().len(), 3);
+    }
+
+    #[test]
+    fn leaderboards_are_saved_as_lists_of_entries() {
+        let mut leaderboards = Leaderboards::default();
+        leaderboards.board_mut("marathon standard").insert(entry(100, 0, "a"), Metric::Score);
+
+        let content = serde_json::to_string(&leaderboards).unwrap();
+        assert!(content.contains(r#""marathon standard":[{"name":"a""#));
+        let leaderboards: Leaderboards = serde_json::from_str(&content).unwrap();
+        assert_eq!(leaderboards.boards["marathon standard"].entries()[0].score, 100);
+    }
+}
@@ -1,33 +1,93 @@
+mod ai;
+mod env;
 mod file_handler;
+mod finesse;
+mod fumen;
+mod game_mode;
+mod history;
+mod leaderboard;
+mod master;
+mod movegen;
+mod piece_set;
+mod practice;
+mod profile;
+mod puzzle;
+mod random;
+mod replay;
+mod rules;
+mod tbp;
 mod tetrimino;
 mod tetris_struct;
 
 extern crate sdl2;
 
-use std::time::SystemTime;
-
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread::sleep;
 use sdl2::EventPump;
 
-use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
 use sdl2::video::{Window, WindowContext};
 
 use sdl2::image::{LoadTexture, InitFlag};
 use sdl2::rect::Rect;
 
-use tetris_struct::{Tetris, is_time_over};
-use file_handler::{load_highscores_and_lines, save_highscores};
+use tetris_struct::{Tetris, CellVisibility, GARBAGE, TICKS_PER_SECOND};
+use rules::{Rules, StackVisibility};
+use file_handler::{update_leaderboards, set_data_dir, set_config_dir, load_zen_session, save_zen_session,
+                   load_piece_set, load_puzzle_pack, load_puzzle_packs, load_solved_puzzles, save_solved_puzzle,
+                   load_openers, save_game, has_saved_game, load_saved_game, save_replay, load_replay,
+                   load_profiles, update_profiles, append_history, load_history, export_history_csv};
+use master::Master;
+use puzzle::Puzzle;
+use tetrimino::Tetrimino;
+use piece_set::PieceSet;
+use practice::Practice;
+use profile::{Handling, Profile, Profiles, Skin};
+use history::Record;
+use ai::Ai;
+use env::EnvConfig;
+use tbp::Bot;
+use leaderboard::{Entry, Leaderboard, Leaderboards, Metric};
+use replay::{Input, Player};
+use game_mode::{GameMode, SPRINT_DEFAULT_LINES, ULTRA_DEFAULT_SECONDS, DIG_DEFAULT_LINES,
+                DIG_DEFAULT_MESSINESS, format_time, frames_to_millis, millis_to_frames};
 
 const TETRIS_HEIGHT: usize = 40;
-const NB_HIGHSCORES: usize = 5;
 
 const WIDTH: u32 = 600;
 const HEIGHT: u32 = 800;
 
+const FADING_DEFAULT_SECONDS: f32 = 3.0;
+const REVEAL_LINE_DELAY: Duration = Duration::from_millis(80);
+
+const MENU_VISIBLE_ITEMS: usize = 12;
+const MENU_TEXT_HEIGHT: u32 = 40;
+const DEFAULT_PUZZLE_PACK: &str = "basics";
+const PREVIEW_PIECES: usize = 5;
+
+const REPLAY_MIN_SPEED: f64 = 0.25;
+const REPLAY_MAX_SPEED: f64 = 8.0;
+const REPLAY_SEEK_TICKS: u64 = 5 * TICKS_PER_SECOND;
+const PROFILE_NAME_LENGTH: usize = 16;
+const STATS_TEXT_HEIGHT: u32 = 24;
+// ticks between two inputs of the AI, in the demo and as an opponent by default
+const DEMO_AI_DELAY: u32 = 4;
+const VERSUS_AI_DELAY: u32 = 10;
+const AI_DEFAULT_LOOKAHEAD: usize = 1;
+const BENCHMARK_DEFAULT_GAMES: u64 = 10;
+// the games of the benchmark stop there if the AI didn't top out before
+const BENCHMARK_MAX_PIECES: u32 = 1000;
+// size of a mino of the opponent's board
+const OPPONENT_CASE_SIZE: usize = 8;
+// milliseconds a bot can think about a piece before it's considered stuck
+const BOT_DEFAULT_TIMEOUT: u64 = 1000;
+// the settings cycle through these values, in milliseconds
+const DAS_CHOICES: [u64; 7] = [50, 83, 100, 133, 167, 200, 250];
+const ARR_CHOICES: [u64; 6] = [0, 17, 33, 50, 67, 83];
+
 #[derive(Clone, Copy)]
 enum TextureColor {
     Green,
@@ -55,108 +115,1266 @@ fn create_texture_from_text<'a>(texture_creator: &'a TextureCreator<WindowContex
     } else { None }
 }
 
-fn handle_events(tetris: &mut Tetris, quit: &mut bool, timer: &mut SystemTime,
-                 event_pump: &mut EventPump) -> bool {
-    let mut make_permanent = false;
+fn draw_text(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+             font: &sdl2::ttf::Font, text: &str, x: i32, y: i32, height: u32) {
+    if let Some(texture) = create_texture_from_text(texture_creator, font, text, 255, 255, 255) {
+        let query = texture.query();
+        let width = query.width * height / query.height;
+        canvas.copy(&texture, None, Rect::new(x, y, width, height))
+            .expect("Couldn't copy text into window");
+    }
+}
+
+fn texture_index(case: u8, textures: &[Texture]) -> usize {
+    // the garbage texture comes after the textures of the pieces
+    if case == GARBAGE { textures.len() - 1 } else { case as usize - 1 }
+}
+
+fn draw_board(canvas: &mut Canvas<Window>, border: &Texture, grid: &Texture,
+              textures: &mut [Texture], tetris: &Tetris, grid_x: i32, grid_y: i32) {
+    canvas.set_draw_color(Color::RGB(255, 0, 0));
+    canvas.clear();
+    canvas.copy(border, None,
+                Rect::new(grid_x - 10, grid_y - 10,
+                          TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20))
+        .expect("Couldn't copy texture into window");
+    canvas.copy(grid, None,
+                Rect::new(grid_x, grid_y,
+                          TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16))
+        .expect("Couldn't copy texture into window");
+
+    let case_size = TETRIS_HEIGHT / tetris.rules.scale();
+    for (line_nb, line) in tetris.game_map.iter().enumerate() {
+        for (case_nb, case) in line.iter().enumerate() {
+            let rect = Rect::new(grid_x + case_nb as i32 * case_size as i32,
+                                 grid_y + line_nb as i32 * case_size as i32,
+                                 case_size as u32, case_size as u32);
+
+            match tetris.cell_visibility(case_nb, line_nb) {
+                CellVisibility::Hidden => {}
+                CellVisibility::Visible => {
+                    canvas.copy(&textures[texture_index(*case, textures)], None, rect)
+                        .expect("Couldn't copy texture into window");
+                }
+                CellVisibility::Faded(alpha) => {
+                    let texture = &mut textures[texture_index(*case, textures)];
+                    texture.set_alpha_mod(alpha);
+                    canvas.copy(texture, None, rect)
+                        .expect("Couldn't copy texture into window");
+                    texture.set_alpha_mod(255);
+                }
+                CellVisibility::Outline => {
+                    canvas.set_draw_color(Color::RGB(255, 255, 255));
+                    canvas.draw_rect(rect).expect("Couldn't draw outline into window");
+                }
+            }
+        }
+    }
+}
+
+fn draw_piece(canvas: &mut Canvas<Window>, textures: &[Texture], tetris: &Tetris, grid_x: i32, grid_y: i32) {
+    let case_size = TETRIS_HEIGHT / tetris.rules.scale();
 
-    if let Some(ref mut piece) = tetris.current_piece {
-        let mut tmp_x = piece.x;
-        let mut tmp_y = piece.y;
+    if let Some(ref piece) = tetris.current_piece {
+        for (line_nb, line) in piece.states[piece.current_state as usize]
+            .iter().enumerate() {
+            for (case_nb, case) in line.iter().enumerate() {
+                if *case == 0 {
+                    continue;
+                }
+                canvas.copy(&textures[texture_index(*case, textures)], None,
+                            Rect::new(grid_x + (piece.x + case_nb as isize) as i32 * case_size as i32,
+                                      grid_y + (piece.y + line_nb) as i32 * case_size as i32,
+                                      case_size as u32, case_size as u32))
+                    .expect("Couldn't copy texture into window");
+            }
+        }
+    }
+}
+
+/// Draws the outline of the placement suggested to the player.
+fn draw_suggestion(canvas: &mut Canvas<Window>, tetris: &Tetris, piece: &Tetrimino, grid_x: i32, grid_y: i32) {
+    let case_size = TETRIS_HEIGHT / tetris.rules.scale();
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    for (line_nb, line) in piece.states[piece.current_state as usize].iter().enumerate() {
+        for (case_nb, _) in line.iter().enumerate().filter(|(_, case)| **case != 0) {
+            canvas.draw_rect(Rect::new(grid_x + (piece.x + case_nb as isize) as i32 * case_size as i32,
+                                       grid_y + (piece.y + line_nb) as i32 * case_size as i32,
+                                       case_size as u32, case_size as u32))
+                .expect("Couldn't draw the suggestion into window");
+        }
+    }
+}
+
+/// Draws the game of the opponent in small, with its stack fully visible.
+fn draw_opponent(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+                 font: &sdl2::ttf::Font, textures: &[Texture], opponent: &Tetris, x: i32, y: i32) {
+    let case_size = OPPONENT_CASE_SIZE / opponent.rules.scale();
+    let rect = |case_x: isize, case_y: usize| {
+        Rect::new(x + case_x as i32 * case_size as i32, y + case_y as i32 * case_size as i32,
+                  case_size as u32, case_size as u32)
+    };
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.fill_rect(Rect::new(x, y, (opponent.game_map[0].len() * case_size) as u32,
+                               (opponent.game_map.len() * case_size) as u32))
+        .expect("Couldn't draw the opponent's board");
+    for (line_nb, line) in opponent.game_map.iter().enumerate() {
+        for (case_nb, case) in line.iter().enumerate().filter(|(_, case)| **case != 0) {
+            canvas.copy(&textures[texture_index(*case, textures)], None, rect(case_nb as isize, line_nb))
+                .expect("Couldn't copy texture into window");
+        }
+    }
+    if let Some(ref piece) = opponent.current_piece {
+        for (line_nb, line) in piece.states[piece.current_state as usize].iter().enumerate() {
+            for (case_nb, case) in line.iter().enumerate().filter(|(_, case)| **case != 0) {
+                canvas.copy(&textures[texture_index(*case, textures)], None,
+                            rect(piece.x + case_nb as isize, piece.y + line_nb))
+                    .expect("Couldn't copy texture into window");
+            }
+        }
+    }
+    let bottom = y + (opponent.game_map.len() * case_size) as i32;
+    draw_text(canvas, texture_creator, font, "AI", x, bottom + 5, 20);
+    draw_text(canvas, texture_creator, font, &format!("{} lines", opponent.nb_lines), x, bottom + 25, 16);
+}
+
+/// Draws the timer and the information on the game of its mode.
+fn draw_hud(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+            font: &sdl2::ttf::Font, tetris: &Tetris, grid_x: i32) {
+    let (time, info) = match tetris.mode {
+        GameMode::Sprint { target_lines, .. } =>
+            (tetris.elapsed_millis(), format!("Lines: {}/{}", tetris.nb_lines, target_lines)),
+        GameMode::Ultra { time_limit } =>
+            (time_limit.saturating_sub(tetris.elapsed_millis()), format!("Score: {}", tetris.score)),
+        GameMode::Dig { .. } =>
+            (tetris.elapsed_millis(), format!("Garbage: {}", tetris.garbage_left())),
+        GameMode::Master(ref master) =>
+            (tetris.elapsed_millis(), format!("Lv {} - {}", master.level, master.grade(tetris.score))),
+        GameMode::Puzzle(ref puzzle) =>
+            (tetris.elapsed_millis(), format!("Next: {}", puzzle.remaining())),
+        GameMode::Practice(ref practice) =>
+            (tetris.elapsed_millis(), format!("Next: {}", practice.preview(PREVIEW_PIECES))),
+        GameMode::Versus { received, .. } =>
+            (tetris.elapsed_millis(), format!("Received: {}", received)),
+        GameMode::Marathon | GameMode::Zen =>
+            (tetris.elapsed_millis(), format!("Lines: {}", tetris.nb_lines)),
+    };
+    let status = match tetris.mode {
+        GameMode::Puzzle(ref puzzle) => Some(puzzle.goal.description()),
+        GameMode::Practice(ref practice) if practice.is_diverged() =>
+            Some(format!("{}: diverged!", practice.opener)),
+        GameMode::Practice(ref practice) => Some(format!("{}: on track", practice.opener)),
+        _ => None,
+    };
+    if let Some(status) = status {
+        draw_text(canvas, texture_creator, font, &status, grid_x, HEIGHT as i32 - 60, 30);
+    }
+    if tetris.rules.hold {
+        let held = tetris.held_piece().map(|name| name.to_string()).unwrap_or_else(|| "-".to_owned());
+        draw_text(canvas, texture_creator, font, &format!("Hold: {}", held),
+                  WIDTH as i32 - 140, HEIGHT as i32 - 60, 30);
+    }
+    draw_text(canvas, texture_creator, font, &format_time(time), grid_x, 15, 40);
+    draw_text(canvas, texture_creator, font, &info, WIDTH as i32 / 2, 15, 40);
+    draw_text(canvas, texture_creator, font, &efficiency(tetris), grid_x, 54, 16);
+}
 
+/// Pieces per second, keys per piece and finesse faults.
+fn efficiency(tetris: &Tetris) -> String {
+    format!("{:.2} PPS - {:.2} KPP - {} finesse faults", tetris.pps(), tetris.kpp(), tetris.finesse_faults)
+}
+
+/// Reveals the whole stack line by line, from the bottom.
+fn reveal_stack(canvas: &mut Canvas<Window>, border: &Texture, grid: &Texture,
+                textures: &mut [Texture], tetris: &mut Tetris, grid_x: i32, grid_y: i32) {
+    while tetris.reveal_line() {
+        draw_board(canvas, border, grid, textures, tetris, grid_x, grid_y);
+        canvas.present();
+        sleep(REVEAL_LINE_DELAY);
+    }
+    sleep(Duration::from_secs(1));
+}
+
+/// The textures of the board: its grid, its border, then the minos of every piece of the set with
+/// the skin and the garbage minos.
+fn create_board_textures<'a>(canvas: &mut Canvas<Window>, texture_creator: &'a TextureCreator<WindowContext>,
+                             piece_set: &PieceSet, skin: Skin) -> (Texture<'a>, Texture<'a>, Vec<Texture<'a>>) {
+    let grid = create_texture_rect(canvas, texture_creator, 0, 0, 0,
+                                   TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16)
+        .expect("Failed to create a texture");
+
+    let border = create_texture_rect(canvas, texture_creator, 255, 255, 255,
+                                     TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20)
+        .expect("Failed to create a texture");
+
+    let mut textures = piece_set.colors().iter()
+        .map(|&color| {
+            let (r, g, b) = skin.color(color);
+            create_texture_rect(canvas, texture_creator, r, g, b, TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32)
+                .expect("Failed to create a texture")
+        })
+        .collect::<Vec<_>>();
+    textures.push(create_texture_rect(canvas, texture_creator, 130, 130, 130,
+                                      TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32)
+        .expect("Failed to create a texture"));
+    for texture in textures.iter_mut() {
+        texture.set_blend_mode(BlendMode::Blend);
+    }
+    (grid, border, textures)
+}
+
+/// Lets the player pick one of `items` with the arrow keys and Enter. Returns None if they go back
+/// with Escape.
+fn choose_item(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+               font: &sdl2::ttf::Font, event_pump: &mut EventPump, title: &str,
+               items: &[String]) -> Option<usize> {
+    let mut selected = 0;
+
+    loop {
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit { .. } |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    *quit = true;
-                    break;
+                Event::Quit { .. } => std::process::exit(0),
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return None,
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } if !items.is_empty() =>
+                    return Some(selected),
+                Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
+                    selected = selected.saturating_sub(1);
                 }
                 Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                    *timer = SystemTime::now();
-                    break;
+                    selected = (selected + 1).min(items.len().saturating_sub(1));
                 }
-                Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                    tmp_x += 1;
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        draw_text(canvas, texture_creator, font, title, 40, 40, MENU_TEXT_HEIGHT + 20);
+        // the list scrolls so the selected item is always visible
+        let first = selected.saturating_sub(MENU_VISIBLE_ITEMS - 1);
+        for (i, item) in items.iter().enumerate().skip(first).take(MENU_VISIBLE_ITEMS) {
+            let text = format!("{} {}", if i == selected { ">" } else { " " }, item);
+            draw_text(canvas, texture_creator, font, &text, 40,
+                      140 + ((i - first) as u32 * (MENU_TEXT_HEIGHT + 10)) as i32, MENU_TEXT_HEIGHT);
+        }
+        canvas.present();
+        sleep(Duration::new(0, 1_000_000u32) / 60);
+    }
+}
+
+/// Lets the player type a line of text, ended with Enter. Returns None if they go back with Escape.
+fn enter_text(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+              font: &sdl2::ttf::Font, event_pump: &mut EventPump, title: &str) -> Option<String> {
+    let mut text = String::new();
+
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => std::process::exit(0),
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return None,
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } if !text.trim().is_empty() =>
+                    return Some(text.trim().to_owned()),
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                    text.pop();
                 }
-                Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                    tmp_x -= 1;
+                Event::TextInput { text: ref input, .. } => {
+                    let room = PROFILE_NAME_LENGTH.saturating_sub(text.chars().count());
+                    text.extend(input.chars().take(room));
                 }
-                Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-                    piece.rotate(&tetris.game_map);
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        draw_text(canvas, texture_creator, font, title, 40, 40, MENU_TEXT_HEIGHT + 20);
+        draw_text(canvas, texture_creator, font, &format!("{}_", text), 40, 140, MENU_TEXT_HEIGHT);
+        canvas.present();
+        sleep(Duration::new(0, 1_000_000u32) / 60);
+    }
+}
+
+/// Waits for the player to press a key. Returns None if they go back with Escape.
+fn read_key(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+            font: &sdl2::ttf::Font, event_pump: &mut EventPump, title: &str) -> Option<Keycode> {
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => std::process::exit(0),
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return None,
+                Event::KeyDown { keycode: Some(keycode), .. } => return Some(keycode),
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        draw_text(canvas, texture_creator, font, title, 40, 40, MENU_TEXT_HEIGHT + 20);
+        canvas.present();
+        sleep(Duration::new(0, 1_000_000u32) / 60);
+    }
+}
+
+/// Shows lines of text until the player goes back with Escape or Enter.
+fn show_lines(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+              font: &sdl2::ttf::Font, event_pump: &mut EventPump, title: &str, lines: &[String]) {
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => std::process::exit(0),
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } |
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } => return,
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        draw_text(canvas, texture_creator, font, title, 40, 40, MENU_TEXT_HEIGHT + 20);
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(canvas, texture_creator, font, line, 40, 140 + (i as u32 * (STATS_TEXT_HEIGHT + 6)) as i32,
+                      STATS_TEXT_HEIGHT);
+        }
+        canvas.present();
+        sleep(Duration::new(0, 1_000_000u32) / 60);
+    }
+}
+
+/// Shows the statistics of the profile in every mode it played, and exports its games to CSV.
+fn show_statistics(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+                   font: &sdl2::ttf::Font, event_pump: &mut EventPump, profile: &Profile) {
+    let history = load_history();
+    let records = history.iter().filter(|record| record.profile == profile.name).collect::<Vec<_>>();
+    let modes = history::by_mode(&records);
+    let mut items = modes.iter()
+        .map(|(label, records)| format!("{} ({} games)", label, records.len()))
+        .collect::<Vec<_>>();
+    items.push("Export to CSV".to_owned());
+
+    let title = format!("Statistics of {}", profile.name);
+    while let Some(index) = choose_item(canvas, texture_creator, font, event_pump, &title, &items) {
+        match modes.iter().nth(index) {
+            Some((label, records)) =>
+                show_lines(canvas, texture_creator, font, event_pump, label, &history::summary(records)),
+            None => match export_history_csv(&records) {
+                Ok(file_name) => println!("History exported to {}", file_name),
+                Err(e) => println!("Couldn't export the history: {}", e),
+            },
+        }
+    }
+}
+
+/// Lets the player switch to another profile or create one.
+fn choose_profile(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+                  font: &sdl2::ttf::Font, event_pump: &mut EventPump, profiles: &mut Profiles) {
+    let mut items = profiles.profiles.iter().map(|profile| profile.name.clone()).collect::<Vec<_>>();
+    items.push("New profile".to_owned());
+
+    let index = match choose_item(canvas, texture_creator, font, event_pump, "Profiles", &items) {
+        Some(index) => index,
+        None => return,
+    };
+    let name = match profiles.profiles.get(index) {
+        Some(profile) => profile.name.clone(),
+        None => match enter_text(canvas, texture_creator, font, event_pump, "Profile name") {
+            Some(name) => name,
+            None => return,
+        },
+    };
+    match update_profiles(|profiles| profiles.select_or_add(&name)) {
+        Ok(updated) => *profiles = updated,
+        Err(e) => println!("Couldn't save the profiles: {}", e),
+    }
+}
+
+/// The choice following `value`, back to the first one after the last one.
+fn next_choice(choices: &[u64], value: u64) -> u64 {
+    choices.iter().cloned().find(|&choice| choice > value).unwrap_or(choices[0])
+}
+
+/// Lets the player change the skin, the handling and the key bindings of their profile.
+fn edit_settings(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+                 font: &sdl2::ttf::Font, event_pump: &mut EventPump, profiles: &mut Profiles) {
+    loop {
+        let mut profile = profiles.current().clone();
+        let mut items = vec![format!("Skin: {}", profile.skin.name()),
+                             format!("DAS: {} ms", profile.handling.das),
+                             format!("ARR: {} ms", profile.handling.arr)];
+        items.extend(Input::ALL.iter()
+            .map(|input| format!("{}: {}", input.name(), profile.bindings.keys(*input).join(", "))));
+
+        let title = format!("Settings of {}", profile.name);
+        match choose_item(canvas, texture_creator, font, event_pump, &title, &items) {
+            Some(0) => profile.skin = profile.skin.next(),
+            Some(1) => profile.handling.das = next_choice(&DAS_CHOICES, profile.handling.das),
+            Some(2) => profile.handling.arr = next_choice(&ARR_CHOICES, profile.handling.arr),
+            Some(index) => {
+                let input = Input::ALL[index - 3];
+                let title = format!("Press a key to {}", input.name().to_lowercase());
+                match read_key(canvas, texture_creator, font, event_pump, &title) {
+                    Some(keycode) => profile.bindings.bind(input, &keycode.name()),
+                    None => continue,
                 }
-                Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                    let x = piece.x;
-                    let mut y = piece.y;
+            }
+            None => return,
+        }
+        // the records of the profile may have changed in another instance in the meantime
+        match update_profiles(|profiles| {
+            profiles.select_or_add(&profile.name);
+            let current = profiles.current_mut();
+            current.skin = profile.skin;
+            current.handling = profile.handling;
+            current.bindings = profile.bindings;
+        }) {
+            Ok(updated) => *profiles = updated,
+            Err(e) => println!("Couldn't save the profiles: {}", e),
+        }
+    }
+}
+
+/// How a game is started from the title screen.
+enum Start {
+    New(GameMode),
+    Continue(Box<Tetris>),
+    // the AI plays on its own until a key is pressed
+    Demo,
+}
 
-                    while piece.change_position(&tetris.game_map, x, y + 1) == true {
-                        y += 1;
+fn title_menu(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+              font: &sdl2::ttf::Font, event_pump: &mut EventPump, piece_set: &PieceSet,
+              profiles: &mut Profiles) -> Option<Start> {
+    loop {
+        let mut items = ["Marathon", "Sprint", "Ultra", "Dig", "Zen", "Master", "Puzzles", "Opener practice",
+                         "Versus AI", "Demo"]
+            .iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        items.push("Statistics".to_owned());
+        items.push(format!("Profile: {}", profiles.current().name));
+        items.push("Settings".to_owned());
+        // the saved game comes first, when there's one
+        let saved = has_saved_game();
+        if saved {
+            items.insert(0, "Continue".to_owned());
+        }
+
+        let index = choose_item(canvas, texture_creator, font, event_pump, "Tetris", &items)?;
+        if saved && index == 0 {
+            match load_saved_game() {
+                Ok(tetris) => return Some(Start::Continue(Box::new(tetris))),
+                Err(e) => {
+                    println!("Couldn't load the saved game: {}", e);
+                    continue;
+                }
+            }
+        }
+        let mode = match index - saved as usize {
+            0 => GameMode::Marathon,
+            1 => GameMode::sprint(SPRINT_DEFAULT_LINES),
+            2 => GameMode::ultra(ULTRA_DEFAULT_SECONDS),
+            3 => GameMode::Dig { garbage_lines: DIG_DEFAULT_LINES, messiness: DIG_DEFAULT_MESSINESS },
+            4 => GameMode::Zen,
+            5 => GameMode::Master(Master::new()),
+            6 => {
+                let puzzle = match browse_puzzles(canvas, texture_creator, font, event_pump) {
+                    Some(puzzle) => puzzle,
+                    None => continue,
+                };
+                // back to the title menu
+                if let Err(e) = puzzle.check(piece_set) {
+                    println!("Couldn't start the puzzle: {}", e);
+                    continue;
+                }
+                GameMode::Puzzle(puzzle)
+            }
+            7 => {
+                let openers = match load_openers() {
+                    Ok(openers) => openers,
+                    Err(e) => {
+                        println!("Couldn't load the openers: {}", e);
+                        continue;
+                    }
+                };
+                let names = openers.iter().map(|opener| opener.name.clone()).collect::<Vec<_>>();
+                let index = match choose_item(canvas, texture_creator, font, event_pump, "Openers", &names) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                match Practice::new(&openers[index], piece_set) {
+                    Ok(practice) => GameMode::Practice(practice),
+                    Err(e) => {
+                        println!("Couldn't practice the opener: {}", e);
+                        continue;
                     }
-                    make_permanent = true;
                 }
-                _ => {}
             }
-            if !make_permanent {
-                if piece.change_position(&tetris.game_map, tmp_x, tmp_y) == false
-                    && tmp_y != piece.y {
-                    make_permanent = true;
+            8 => GameMode::versus(),
+            9 => return Some(Start::Demo),
+            10 => {
+                show_statistics(canvas, texture_creator, font, event_pump, profiles.current());
+                continue;
+            }
+            11 => {
+                choose_profile(canvas, texture_creator, font, event_pump, profiles);
+                continue;
+            }
+            _ => {
+                edit_settings(canvas, texture_creator, font, event_pump, profiles);
+                continue;
+            }
+        };
+        return Some(Start::New(mode));
+    }
+}
+
+/// Lists the puzzle packs, then the puzzles of the chosen pack along with the ones already
+/// solved.
+fn browse_puzzles(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+                  font: &sdl2::ttf::Font, event_pump: &mut EventPump) -> Option<Puzzle> {
+    let packs = load_puzzle_packs();
+    let pack_names = packs.iter()
+        .map(|pack| format!("{} ({} puzzles)", pack.name, pack.puzzles.len()))
+        .collect::<Vec<_>>();
+
+    loop {
+        let pack = &packs[choose_item(canvas, texture_creator, font, event_pump, "Puzzle packs",
+                                      &pack_names)?];
+        let solved = load_solved_puzzles();
+        let puzzle_names = pack.puzzles.iter()
+            .map(|puzzle| format!("{} {}", if solved.contains(&puzzle.id()) { "[x]" } else { "[ ]" },
+                                  puzzle.name))
+            .collect::<Vec<_>>();
+
+        if let Some(index) = choose_item(canvas, texture_creator, font, event_pump, &pack.name,
+                                         &puzzle_names) {
+            return Some(pack.puzzles[index].clone());
+        }
+    }
+}
+
+/// Prints the game map and the current piece as a fumen, to share them.
+fn print_fumen(game_map: &[Vec<u8>], piece: &Tetrimino, piece_set: &PieceSet) {
+    let page = fumen::Page {
+        game_map: game_map.to_vec(),
+        piece: Some(piece.clone()),
+        comment: String::new(),
+    };
+    match fumen::encode(&[page], piece_set) {
+        Ok(data) => println!("{}", data),
+        Err(e) => println!("Couldn't export the board: {}", e),
+    }
+}
+
+/// Repeats the sideways move of the key held, after the DAS of the profile then every ARR.
+#[derive(Default)]
+struct AutoShift {
+    // the move held and the tick of its next repetition
+    held: Option<(Input, u64)>,
+}
+
+impl AutoShift {
+    fn press(&mut self, input: Input, frame: u64, handling: &Handling) {
+        self.held = Some((input, frame + millis_to_frames(handling.das)));
+    }
+
+    fn release(&mut self, input: Input) {
+        if matches!(self.held, Some((held, _)) if held == input) {
+            self.held = None;
+        }
+    }
+
+    /// Plays the repeated moves due by the current tick.
+    fn update(&mut self, tetris: &mut Tetris, handling: &Handling) {
+        let arr = millis_to_frames(handling.arr);
+
+        if let Some((input, ref mut next)) = self.held {
+            while *next <= tetris.frame {
+                if !tetris.can_shift(input) {
+                    // the shift stays charged for the next piece
+                    *next = tetris.frame;
+                    break;
+                }
+                tetris.apply(input, true);
+                // with an ARR of 0 the piece goes straight to the wall
+                if arr > 0 {
+                    *next += arr;
                 }
             }
         }
-        if make_permanent {
-            tetris.make_permanent();
+    }
+}
+
+fn handle_events(tetris: &mut Tetris, quit: &mut bool, event_pump: &mut EventPump, profile: &Profile,
+                 auto_shift: &mut AutoShift) -> bool {
+    let mut make_permanent = false;
+
+    if tetris.current_piece.is_none() {
+        return false;
+    }
+    for event in event_pump.poll_iter() {
+        let (keycode, repeat) = match event {
+            Event::Quit { .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                *quit = true;
+                break;
+            }
+            Event::KeyDown { keycode: Some(keycode), repeat, .. } => (keycode, repeat),
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                if let Some(input) = profile.bindings.input(&keycode.name()) {
+                    auto_shift.release(input);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let input = match profile.bindings.input(&keycode.name()) {
+            Some(input) => input,
+            None => {
+                if keycode == Keycode::F {
+                    if let Some(ref piece) = tetris.current_piece {
+                        print_fumen(&tetris.game_map, piece, &tetris.rules.piece_set);
+                    }
+                }
+                continue;
+            }
+        };
+        match input {
+            // the sideways moves repeat with the handling of the profile, not the key repeat
+            Input::Left | Input::Right if repeat => {}
+            Input::Left | Input::Right => {
+                make_permanent |= tetris.apply(input, false);
+                auto_shift.press(input, tetris.frame, &profile.handling);
+            }
+            _ => make_permanent |= tetris.apply(input, repeat),
         }
     }
+    auto_shift.update(tetris, &profile.handling);
     make_permanent
 }
 
-fn print_game_info(tetris: &mut Tetris) {
-    let mut new_highest_highscore = true;
-    let mut new_highest_lines_sent = true;
+fn print_game_info(tetris: &mut Tetris, profile: &Profile) {
+    // the garbage received from the opponent isn't in the replays, they couldn't be played back
+    let versus = matches!(tetris.mode, GameMode::Versus { .. });
+    let replay = if versus { None } else { save_replay(&tetris.replay()) };
+    let entry = Entry::new(tetris, &profile.name, replay.clone());
+
+    // a Zen session is only over once the stack tops out
+    if (tetris.mode.is_ranked() || tetris.is_game_over() || tetris.is_finished()) &&
+        !append_history(&Record::new(tetris, &profile.name)) {
+        println!("Couldn't add the game to the history...");
+    }
+
+    println!("Mode: {}", tetris.mode.name());
+    println!("Efficiency: {}", efficiency(tetris));
+    match tetris.mode {
+        GameMode::Marathon => print_marathon_info(tetris, entry),
+        GameMode::Sprint { .. } => print_sprint_info(tetris, entry),
+        GameMode::Ultra { .. } => print_ultra_info(tetris, entry),
+        GameMode::Dig { .. } => print_dig_info(tetris, entry),
+        GameMode::Zen => print_zen_info(tetris),
+        GameMode::Master(_) => print_master_info(tetris, entry),
+        GameMode::Puzzle(_) => print_puzzle_info(tetris),
+        GameMode::Practice(_) => print_practice_info(tetris),
+        GameMode::Versus { .. } => print_versus_info(tetris),
+    }
+    match replay {
+        Some(file_name) => println!("Replay saved to {}", file_name),
+        None if !versus => println!("Couldn't save the replay..."),
+        None => {}
+    }
+}
+
+/// Where a game placed in the leaderboard of its mode and among the games of its profile.
+struct Placement {
+    leaderboard: Leaderboard,
+    // rank in the leaderboard, None if the game didn't make it
+    rank: Option<usize>,
+    personal_best: bool,
+}
 
-    if let Some((mut highscores, mut lines_sent)) =
-        load_highscores_and_lines() {
-        new_highest_highscore = update_vec(&mut highscores, tetris.score);
-        new_highest_lines_sent = update_vec(&mut lines_sent, tetris.nb_lines);
+/// Adds the game to the leaderboard of its mode and rules if it's good enough, and to the records
+/// of the profile named in the entry.
+fn record_entry(tetris: &Tetris, entry: Entry) -> Placement {
+    let mut placement = Placement {
+        leaderboard: Leaderboard::default(),
+        rank: None,
+        personal_best: false,
+    };
+    let (key, metric) = match (Leaderboards::key(tetris), Metric::of(&tetris.mode)) {
+        (Some(key), Some(metric)) => (key, metric),
+        _ => return placement,
+    };
 
-        if new_highest_lines_sent || new_highest_lines_sent {
-            save_highscores(&highscores, &lines_sent);
+    if let Err(e) = update_profiles(|profiles| {
+        if let Some(profile) = profiles.profiles.iter_mut().find(|profile| profile.name == entry.name) {
+            placement.personal_best = profile.record(&key, entry.clone(), metric);
         }
-    } else {
-        save_highscores(&[tetris.score], &[tetris.nb_lines]);
+    }) {
+        println!("Couldn't update the profiles: {}", e);
+    }
+    match update_leaderboards(|leaderboards| {
+        placement.rank = leaderboards.board_mut(&key).insert(entry, metric);
+        placement.rank.is_some()
+    }) {
+        Ok(mut leaderboards) => placement.leaderboard = leaderboards.board_mut(&key).clone(),
+        Err(e) => println!("Couldn't update the leaderboards: {}", e),
+    }
+    placement
+}
+
+fn print_placement(placement: &Placement) {
+    if let Some(rank) = placement.rank {
+        println!("You placed #{}!", rank);
+    }
+    if placement.personal_best {
+        println!("New personal best!");
     }
+}
 
+fn print_marathon_info(tetris: &mut Tetris, entry: Entry) {
+    let placement = record_entry(tetris, entry);
 
     println!("Game over...");
     println!("Score: {}{}",
              tetris.score,
-             if new_highest_highscore { " [NEW HIGHSCORE]" } else {
+             if placement.rank == Some(1) { " [NEW HIGHSCORE]" } else {
                  ""
              });
-    println!("Number of lines: {}{}",
-             tetris.nb_lines,
-             if new_highest_lines_sent { " [NEW HIGHSCORE]" } else {
+    println!("Number of lines: {}", tetris.nb_lines);
+    println!("Current level:      {}", tetris.current_level);
+    print_placement(&placement);
+    println!("Highscores:");
+    for (i, entry) in placement.leaderboard.entries().iter().enumerate() {
+        println!("  {}. {} - {} lines ({}, {})", i + 1, entry.score, entry.lines, entry.name,
+                 entry.format_date());
+    }
+}
+
+fn print_sprint_info(tetris: &mut Tetris, entry: Entry) {
+    let (target_lines, splits) = match tetris.mode {
+        GameMode::Sprint { target_lines, ref splits } => (target_lines, splits),
+        _ => unreachable!(),
+    };
+
+    if !tetris.is_finished() {
+        println!("Sprint failed...");
+        println!("Number of lines: {}/{}", tetris.nb_lines, target_lines);
+        return;
+    }
+
+    let time = tetris.elapsed_millis();
+    let placement = record_entry(tetris, entry);
+
+    println!("Sprint finished!");
+    println!("Time: {}{}",
+             format_time(time),
+             if placement.rank == Some(1) { " [NEW BEST TIME]" } else {
                  ""
              });
-    println!("Current level:      {}", tetris.current_level);
+    for (i, split) in splits.iter().enumerate() {
+        println!("  {:>3} lines: {}", (i + 1) * 10, format_time(*split));
+    }
+    print_placement(&placement);
+    println!("Best times:");
+    for (i, best) in placement.leaderboard.entries().iter().enumerate() {
+        println!("  {}. {} ({}, {})", i + 1, format_time(best.duration), best.name, best.format_date());
+    }
 }
 
-fn update_vec(v: &mut Vec<u32>, value: u32) -> bool {
-    if v.len() < NB_HIGHSCORES {
-        v.push(value);
-        v.sort();
-        true
+fn print_ultra_info(tetris: &mut Tetris, entry: Entry) {
+    let placement = record_entry(tetris, entry);
+
+    println!("Time's up!");
+    println!("Score: {}{}",
+             tetris.score,
+             if placement.rank == Some(1) { " [NEW HIGHSCORE]" } else {
+                 ""
+             });
+    println!("Number of lines: {}", tetris.nb_lines);
+    println!("Singles:    {}", tetris.clear_stats.singles);
+    println!("Doubles:    {}", tetris.clear_stats.doubles);
+    println!("Triples:    {}", tetris.clear_stats.triples);
+    println!("Tetrises:   {}", tetris.clear_stats.tetrises);
+    println!("T-spins:    {}", tetris.clear_stats.t_spins);
+    println!("All-clears: {}", tetris.clear_stats.all_clears);
+    if tetris.rules.cascade {
+        println!("Chains:     {} (best: {})", tetris.clear_stats.chains, tetris.clear_stats.best_chain);
+    }
+    print_placement(&placement);
+    println!("Highscores:");
+    for (i, entry) in placement.leaderboard.entries().iter().enumerate() {
+        println!("  {}. {} ({}, {})", i + 1, entry.score, entry.name, entry.format_date());
+    }
+}
+
+fn print_dig_info(tetris: &mut Tetris, entry: Entry) {
+    let garbage_lines = match tetris.mode {
+        GameMode::Dig { garbage_lines, .. } => garbage_lines,
+        _ => unreachable!(),
+    };
+
+    if !tetris.is_finished() {
+        println!("Dig failed...");
+        println!("Garbage lines left: {}/{}", tetris.garbage_left(), garbage_lines);
+        return;
+    }
+
+    let time = tetris.elapsed_millis();
+    let placement = record_entry(tetris, entry);
+
+    println!("All garbage cleared!");
+    println!("Time: {}{}",
+             format_time(time),
+             if placement.rank == Some(1) { " [NEW BEST TIME]" } else {
+                 ""
+             });
+    println!("Number of lines: {}", tetris.nb_lines);
+    print_placement(&placement);
+    println!("Best times:");
+    for (i, best) in placement.leaderboard.entries().iter().enumerate() {
+        println!("  {}. {} ({}, {})", i + 1, format_time(best.duration), best.name, best.format_date());
+    }
+}
+
+fn print_zen_info(tetris: &mut Tetris) {
+    if save_zen_session(tetris) {
+        println!("Session suspended, run Zen mode again to resume it.");
+    } else {
+        println!("Couldn't save the session...");
+    }
+    println!("Score: {}", tetris.score);
+    println!("Number of lines: {}", tetris.nb_lines);
+    println!("Play time: {}", format_time(tetris.elapsed_millis()));
+}
+
+fn print_master_info(tetris: &mut Tetris, entry: Entry) {
+    let placement = record_entry(tetris, entry);
+    let master = match tetris.mode {
+        GameMode::Master(ref master) => master,
+        _ => unreachable!(),
+    };
+
+    println!("{}", if master.is_complete() { "Master mode cleared!" } else { "Game over..." });
+    println!("Grade: {}{}",
+             master.grade(tetris.score),
+             if placement.rank == Some(1) { " [NEW BEST GRADE]" } else {
+                 ""
+             });
+    println!("Level: {}", master.level);
+    println!("Score: {}", tetris.score);
+    println!("Time: {}", format_time(tetris.elapsed_millis()));
+    let mut section_start = 0;
+    for (i, section_end) in master.section_times.iter().enumerate() {
+        println!("  {:>3}-{:<3}: {}", i * 100, i * 100 + 99,
+                 format_time(section_end - section_start));
+        section_start = *section_end;
+    }
+    print_placement(&placement);
+    println!("Best grades:");
+    for (i, entry) in placement.leaderboard.entries().iter().enumerate() {
+        println!("  {}. {} - level {} in {} ({}, {})", i + 1, master.grade(entry.score), entry.level,
+                 format_time(entry.duration), entry.name, entry.format_date());
+    }
+}
+
+fn print_puzzle_info(tetris: &mut Tetris) {
+    let puzzle = match tetris.mode {
+        GameMode::Puzzle(ref puzzle) => puzzle,
+        _ => unreachable!(),
+    };
+
+    println!("Goal: {}", puzzle.goal.description());
+    if puzzle.is_solved() {
+        save_solved_puzzle(&puzzle.id());
+        println!("Puzzle solved!");
+    } else {
+        println!("Puzzle failed...");
+    }
+    println!("Time: {}", format_time(tetris.elapsed_millis()));
+}
+
+fn print_practice_info(tetris: &mut Tetris) {
+    let practice = match tetris.mode {
+        GameMode::Practice(ref practice) => practice,
+        _ => unreachable!(),
+    };
+
+    if practice.is_complete() {
+        println!("{} built!", practice.opener);
+    } else if practice.is_diverged() {
+        println!("Diverged from {} at piece {}...", practice.opener, practice.nb_placed());
+    } else {
+        println!("{} not finished...", practice.opener);
+    }
+    println!("Time: {}", format_time(tetris.elapsed_millis()));
+}
+
+fn print_versus_info(tetris: &mut Tetris) {
+    let (received, won) = match tetris.mode {
+        GameMode::Versus { received, won } => (received, won),
+        _ => unreachable!(),
+    };
+
+    println!("{}", if won { "You won!" } else if tetris.is_game_over() { "You lost..." } else { "Game abandoned." });
+    println!("Lines sent: {}", tetris.clear_stats.attack);
+    println!("Lines received: {}", received);
+    println!("Number of lines: {}", tetris.nb_lines);
+    println!("Time: {}", format_time(tetris.elapsed_millis()));
+}
+
+/// Prints the result of the game, which isn't recorded if a bot played or advised the player.
+fn print_result(tetris: &mut Tetris, profile: &Profile, assisted: bool) {
+    if assisted {
+        println!("Played with a bot, the game isn't recorded.");
+        print_replay_result(tetris);
     } else {
-        for entry in v.iter_mut() {
-            if value > *entry {
-                *entry = value;
-                return true;
+        print_game_info(tetris, profile);
+    }
+}
+
+/// Launches a bot and tells the player about it, None if it couldn't be launched.
+fn launch_bot(command: &str, role: &str, timeout: Duration) -> Option<Bot> {
+    match Bot::launch(command, timeout) {
+        Ok(bot) => {
+            println!("{} {} by {} is {}.", bot.name, bot.version, bot.author, role);
+            Some(bot)
+        }
+        Err(e) => {
+            println!("Couldn't launch the bot: {}", e);
+            None
+        }
+    }
+}
+
+/// Prints the result of a replay played through, to check it against a claimed one.
+fn print_replay_result(tetris: &Tetris) {
+    println!("Mode: {}", tetris.mode.name());
+    println!("{}", if tetris.is_finished() {
+        "Completed"
+    } else if tetris.is_game_over() {
+        "Game over"
+    } else {
+        "Stopped"
+    });
+    println!("Score: {}", tetris.score);
+    println!("Number of lines: {}", tetris.nb_lines);
+    println!("Time: {}", format_time(tetris.elapsed_millis()));
+    println!("Efficiency: {}", efficiency(tetris));
+}
+
+
+struct Options {
+    // None to choose the mode from the title menu
+    mode: Option<GameMode>,
+    rules: Rules,
+    // reveal the stack at the end of the game
+    reveal: bool,
+    // fumen of the starting map
+    fumen: Option<String>,
+    // replay file to watch, or to verify without watching it
+    replay: Option<String>,
+    verify: bool,
+    // pieces of the preview the AI looks at, and ticks between two of its inputs as an opponent
+    lookahead: usize,
+    ai_delay: u32,
+    // number of games for the AI to play without a window, to measure how well it plays
+    benchmark: Option<u64>,
+    // commands launching a bot playing the game, and one suggesting placements to the player
+    bot: Option<String>,
+    advisor: Option<String>,
+    bot_timeout: Duration,
+    // number of environments served to an agent on the standard input and output
+    env: Option<usize>,
+}
+
+fn parse_options() -> Options {
+    let mut args = Vec::new();
+    let mut rules = Rules::default();
+    let mut reveal = false;
+    let mut fumen = None;
+    let mut replay = None;
+    let mut verify = false;
+    let mut lookahead = AI_DEFAULT_LOOKAHEAD;
+    let mut ai_delay = VERSUS_AI_DELAY;
+    let mut benchmark = None;
+    let mut bot = None;
+    let mut advisor = None;
+    let mut bot_timeout = BOT_DEFAULT_TIMEOUT;
+    let mut env = None;
+    let mut iter = std::env::args().skip(1);
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--invisible" => rules.stack_visibility = StackVisibility::Invisible,
+            "--fading" => {
+                let seconds = iter.next().and_then(|seconds| seconds.parse().ok())
+                    .unwrap_or(FADING_DEFAULT_SECONDS);
+                rules.stack_visibility = StackVisibility::Fading {
+                    delay: (seconds * TICKS_PER_SECOND as f32) as u32,
+                };
+            }
+            "--reveal" => reveal = true,
+            "--big" => rules.big = true,
+            "--cascade" => rules.cascade = true,
+            "--hold" => rules.hold = true,
+            "--pieces" => {
+                let name = iter.next().unwrap_or_else(|| "standard".to_owned());
+                rules.piece_set = match load_piece_set(&name) {
+                    Ok(piece_set) => piece_set,
+                    Err(e) => {
+                        println!("Couldn't load the piece set: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--fumen" => fumen = iter.next(),
+            "--replay" => replay = iter.next(),
+            "--data-dir" => {
+                if let Some(dir) = iter.next() {
+                    set_data_dir(&dir);
+                }
             }
+            "--config-dir" => {
+                if let Some(dir) = iter.next() {
+                    set_config_dir(&dir);
+                }
+            }
+            "--verify" => {
+                replay = iter.next();
+                verify = true;
+            }
+            "--lookahead" => {
+                lookahead = iter.next().and_then(|pieces| pieces.parse().ok()).unwrap_or(AI_DEFAULT_LOOKAHEAD);
+            }
+            "--ai-delay" => {
+                ai_delay = iter.next().and_then(|ticks| ticks.parse().ok()).unwrap_or(VERSUS_AI_DELAY);
+            }
+            "--benchmark" => {
+                let games = iter.next().and_then(|games| games.parse().ok()).unwrap_or(BENCHMARK_DEFAULT_GAMES);
+                benchmark = Some(games);
+            }
+            "--bot" => bot = iter.next(),
+            "--advisor" => advisor = iter.next(),
+            "--bot-timeout" => {
+                bot_timeout = iter.next().and_then(|millis| millis.parse().ok()).unwrap_or(BOT_DEFAULT_TIMEOUT);
+            }
+            "--env" => env = Some(iter.next().and_then(|envs| envs.parse().ok()).unwrap_or(1)),
+            _ => args.push(arg),
+        }
+    }
+
+    Options {
+        mode: parse_game_mode(&args, &rules),
+        rules,
+        reveal,
+        fumen,
+        replay,
+        verify,
+        lookahead,
+        ai_delay,
+        benchmark,
+        bot,
+        advisor,
+        bot_timeout: Duration::from_millis(bot_timeout),
+        env,
+    }
+}
+
+fn parse_game_mode(args: &[String], rules: &Rules) -> Option<GameMode> {
+    let mode = match args.first()?.as_str() {
+        "sprint" => GameMode::sprint(args.get(1)
+            .and_then(|lines| lines.parse().ok())
+            .unwrap_or(SPRINT_DEFAULT_LINES)),
+        "ultra" => GameMode::ultra(args.get(1)
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(ULTRA_DEFAULT_SECONDS)),
+        "dig" => match GameMode::dig(
+            args.get(1).and_then(|lines| lines.parse().ok()).unwrap_or(DIG_DEFAULT_LINES),
+            args.get(2).and_then(|messiness| messiness.parse().ok()).unwrap_or(DIG_DEFAULT_MESSINESS)) {
+            Ok(mode) => mode,
+            Err(e) => {
+                println!("Couldn't start the dig: {}", e);
+                return None;
+            }
+        },
+        "zen" => GameMode::Zen,
+        "versus" => GameMode::versus(),
+        "master" => GameMode::Master(Master::new()),
+        // the title menu is shown instead of a puzzle or an opener which can't be played
+        "puzzle" => match parse_puzzle(args, rules) {
+            Ok(mode) => mode,
+            Err(e) => {
+                println!("Couldn't start the puzzle: {}", e);
+                return None;
+            }
+        },
+        "practice" => match parse_practice(args, rules) {
+            Ok(mode) => mode,
+            Err(e) => {
+                println!("Couldn't practice the opener: {}", e);
+                return None;
+            }
+        },
+        _ => GameMode::Marathon,
+    };
+    Some(mode)
+}
+
+fn parse_puzzle(args: &[String], rules: &Rules) -> Result<GameMode, String> {
+    let pack = load_puzzle_pack(args.get(1).map(|pack| pack.as_str()).unwrap_or(DEFAULT_PUZZLE_PACK))?;
+    // puzzles are numbered from 1
+    let number = args.get(2).and_then(|number| number.parse::<usize>().ok()).unwrap_or(1);
+    let puzzle = pack.puzzles.get(number.max(1) - 1)
+        .ok_or_else(|| format!("the {} pack has no puzzle {}", pack.name, number))?;
+    puzzle.check(&rules.piece_set)?;
+    Ok(GameMode::Puzzle(puzzle.clone()))
+}
+
+fn parse_practice(args: &[String], rules: &Rules) -> Result<GameMode, String> {
+    let openers = load_openers()?;
+    let opener = match args.get(1) {
+        Some(name) => openers.iter().find(|opener| opener.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("no opener named {}", name))?,
+        None => openers.first().ok_or("no opener in the library")?,
+    };
+    Ok(GameMode::Practice(Practice::new(opener, &rules.piece_set)?))
+}
+
+/// Starts the game given on the command line, or the one chosen from the title menu.
+fn start_game(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+              font: &sdl2::ttf::Font, event_pump: &mut EventPump, options: &Options,
+              profiles: &mut Profiles) -> Option<Tetris> {
+    let start = match options.mode {
+        Some(ref mode) => Start::New(mode.clone()),
+        None => loop {
+            match title_menu(canvas, texture_creator, font, event_pump, &options.rules.piece_set, profiles)? {
+                Start::Demo => show_demo(canvas, texture_creator, font, event_pump, options, profiles.current()),
+                start => break start,
+            }
+        },
+    };
+    let tetris = match start {
+        Start::Continue(tetris) => *tetris,
+        Start::Demo => unreachable!(),
+        Start::New(mode) => {
+            let mut rules = options.rules.clone();
+            if let GameMode::Practice(_) = mode {
+                // the openers are built with the help of hold
+                rules.hold = true;
+            }
+            let mut tetris = match mode {
+                GameMode::Zen => load_zen_session().unwrap_or_else(|| Tetris::new(GameMode::Zen, rules)),
+                mode => Tetris::new(mode, rules),
+            };
+            if let Some(ref data) = options.fumen {
+                // the game starts on an empty board when the fumen can't be read
+                match fumen::decode(data, &tetris.rules.piece_set) {
+                    Ok(pages) => tetris.set_game_map(&pages[0].game_map),
+                    Err(e) => println!("Couldn't read the fumen: {}", e),
+                }
+            }
+            tetris
         }
-        false
+    };
+    Some(tetris)
+}
+
+/// Lets the AI play Marathon on its own, until a key is pressed or it tops out.
+fn show_demo(canvas: &mut Canvas<Window>, texture_creator: &TextureCreator<WindowContext>,
+             font: &sdl2::ttf::Font, event_pump: &mut EventPump, options: &Options, profile: &Profile) {
+    let grid_x = (WIDTH - TETRIS_HEIGHT as u32 * 10) as i32 / 2;
+    let grid_y = (HEIGHT - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
+    let (grid, border, mut textures) = create_board_textures(canvas, texture_creator, &options.rules.piece_set,
+                                                             profile.skin);
+    let mut tetris = Tetris::new(GameMode::Marathon, options.rules.clone());
+    let mut ai = Ai::new(options.lookahead, DEMO_AI_DELAY);
+    let tick_duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_SECOND);
+    let mut last_tick = Instant::now();
+
+    while !tetris.is_game_over() {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => std::process::exit(0),
+                Event::KeyDown { .. } => return,
+                _ => {}
+            }
+        }
+        while last_tick.elapsed() >= tick_duration {
+            last_tick += tick_duration;
+            ai.update(&mut tetris);
+            tetris.tick();
+        }
+
+        draw_board(canvas, &border, &grid, &mut textures, &tetris, grid_x, grid_y);
+        draw_piece(canvas, &textures, &tetris, grid_x, grid_y);
+        draw_hud(canvas, texture_creator, font, &tetris, grid_x);
+        draw_text(canvas, texture_creator, font, "Demo - press any key", grid_x, HEIGHT as i32 - 28, 24);
+        canvas.present();
+        sleep(Duration::new(0, 1_000_000u32) / 60);
     }
 }
 
+/// Lets the AI play games of the mode without a window, as fast as it can, and prints how it did.
+fn benchmark(options: &Options, games: u64) {
+    let mode = options.mode.clone().unwrap_or(GameMode::Marathon);
+    let (mut lines, mut pieces, mut score) = (0, 0, 0);
+    let start = Instant::now();
+
+    println!("Mode: {}", mode.name());
+    println!("Lookahead: {} pieces", options.lookahead);
+    for seed in 0..games {
+        let mut tetris = Tetris::with_seed(mode.clone(), options.rules.clone(), seed);
+        let mut ai = Ai::new(options.lookahead, 0);
+
+        while !tetris.is_game_over() && !tetris.is_finished() && tetris.nb_pieces < BENCHMARK_MAX_PIECES {
+            ai.update(&mut tetris);
+            tetris.tick();
+        }
+        println!("  Game {}: {} lines, {} pieces, score {}{}", seed + 1, tetris.nb_lines, tetris.nb_pieces,
+                 tetris.score, if tetris.is_game_over() { " (topped out)" } else { "" });
+        lines += tetris.nb_lines as u64;
+        pieces += tetris.nb_pieces as u64;
+        score += tetris.score as u64;
+    }
+
+    let games = games.max(1);
+    println!("Average: {} lines, {} pieces, score {}", lines / games, pieces / games, score / games);
+    println!("Time per piece: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0 / pieces.max(1) as f64);
+}
 
 fn main() {
+    let options = parse_options();
+    if options.verify {
+        let played = match options.replay {
+            Some(ref file_name) => load_replay(file_name).and_then(Player::play_through),
+            None => Err("no replay to verify".to_owned()),
+        };
+        match played {
+            Ok(tetris) => print_replay_result(&tetris),
+            Err(e) => {
+                println!("Couldn't play the replay: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(games) = options.benchmark {
+        benchmark(&options, games);
+        return;
+    }
+    if let Some(nb_envs) = options.env {
+        let config = EnvConfig {
+            mode: options.mode.clone().unwrap_or(GameMode::Marathon),
+            rules: options.rules.clone(),
+            ..EnvConfig::default()
+        };
+        // the standard output is the agent's, the errors go to the standard error
+        if let Err(e) = env::serve(config, nb_envs, std::io::stdin().lock(), std::io::stdout().lock()) {
+            eprintln!("The environment stopped: {}", e);
+        }
+        return;
+    }
+
     let sdl_content = sdl2::init().expect("SDL initialization failed");
     let video_subsystem = sdl_content.video()
         .expect("Couldn't get SDL video subsystem");
@@ -166,12 +1384,10 @@ fn main() {
         .expect("SDL TTF initialization failed");
 
     let font = ttf_context.load_font("assets/lucida_sans_italic.ttf", 128)
-        .except("Couldn't load the font");
+        .expect("Couldn't load the font");
 
     //font.set_style(sdl2::ttf::FontStyle::BOLD);
 
-    let mut tetris = Tetris::new();
-    let mut timer = SystemTime::now();
     let mut event_pump = sdl_content.event_pump()
         .expect("Failed to get SDL event pump");
 
@@ -193,102 +1409,165 @@ fn main() {
 
     let texture_creator: TextureCreator<_> = canvas.texture_creator();
 
-    let grid = create_texture_rect(&mut canvas, &texture_creator, 0, 0, 0,
-                                   TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16)
-        .expect("Failed to create a texture");
-
-    let border = create_texture_rect(&mut canvas, &texture_creator, 255, 255, 255,
-                                     TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20)
-        .expect("Failed to create a texture");
+    let mut profiles = load_profiles().unwrap_or_else(|e| {
+        println!("Couldn't load the profiles: {}", e);
+        Profiles::default()
+    });
+    let mut player = match options.replay {
+        Some(ref file_name) => match load_replay(file_name).and_then(Player::new) {
+            Ok(player) => Some(player),
+            Err(e) => {
+                println!("Couldn't play the replay: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let mut tetris = match player {
+        Some(ref mut player) => player.start(),
+        None => match start_game(&mut canvas, &texture_creator, &font, &mut event_pump, &options, &mut profiles) {
+            Some(tetris) => tetris,
+            None => return,
+        },
+    };
+    let profile = profiles.current().clone();
+    let mut auto_shift = AutoShift::default();
 
-    macro_rules! texture {
-        ($r: expr, $g: expr, $b: expr) => (
-            create_texture_rect(&mut canvas, &texture_creator,
-            $r, $g, $b,
-            TETRIS_HEIGHT as u32,
-            TETRIS_HEIGHT as u32).unwrap()
-        );
-    }
+    let (grid, border, mut textures) = create_board_textures(&mut canvas, &texture_creator,
+                                                             &tetris.rules.piece_set, profile.skin);
+    // the AI playing against the player in Versus mode
+    let mut versus = match tetris.mode {
+        GameMode::Versus { .. } => Some((Tetris::new(GameMode::versus(), tetris.rules.clone()),
+                                         Ai::new(options.lookahead, options.ai_delay))),
+        _ => None,
+    };
 
-    let textures = [texture!(255, 69, 69), texture!(255, 220, 69),
-        texture!(237, 150, 37), texture!(171, 99, 237), texture!(77, 149,
-239), texture!(39, 218, 225), texture!(45, 216, 47)];
+    let tick_duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_SECOND);
+    let mut last_tick = Instant::now();
 
+    if let Some(ref mut player) = player {
+        let mut speed: f64 = 1.0;
+        let mut paused = false;
 
-    loop {
-        if is_time_over(&mut tetris, &timer) {
-            let mut make_permanent = false;
-            if let Some(ref mut piece) = tetris.current_piece {
-                let x = piece.x;
-                let y = piece.y + 1;
-                make_permanent = !piece.change_position(&tetris.game_map, x, y);
+        loop {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. } |
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return,
+                    Event::KeyDown { keycode: Some(Keycode::Space), .. } => paused = !paused,
+                    Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
+                        speed = (speed * 2.0).min(REPLAY_MAX_SPEED);
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
+                        speed = (speed / 2.0).max(REPLAY_MIN_SPEED);
+                    }
+                    // one tick at a time while paused
+                    Event::KeyDown { keycode: Some(Keycode::Period), .. } if paused => player.step(&mut tetris),
+                    Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
+                        let tick = tetris.frame.saturating_sub(REPLAY_SEEK_TICKS);
+                        player.seek(&mut tetris, tick);
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
+                        let tick = tetris.frame + REPLAY_SEEK_TICKS;
+                        player.seek(&mut tetris, tick);
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::Home), .. } => player.seek(&mut tetris, 0),
+                    _ => {}
+                }
             }
 
-            if make_permanent {
-                tetris.make_permanent();
+            let step_duration = tick_duration.div_f64(speed);
+            if paused || player.is_over(&tetris) {
+                last_tick = Instant::now();
+            }
+            while last_tick.elapsed() >= step_duration && !player.is_over(&tetris) {
+                last_tick += step_duration;
+                player.step(&mut tetris);
             }
 
-            timer = SystemTime::now();
+            draw_board(&mut canvas, &border, &grid, &mut textures, &tetris, grid_x, grid_y);
+            draw_piece(&mut canvas, &textures, &tetris, grid_x, grid_y);
+            draw_hud(&mut canvas, &texture_creator, &font, &tetris, grid_x);
+            let state = if player.is_over(&tetris) { " (end)" } else if paused { " (paused)" } else { "" };
+            draw_text(&mut canvas, &texture_creator, &font,
+                      &format!("Replay x{}{} - {}", speed, state, format_time(frames_to_millis(player.length()))),
+                      grid_x, HEIGHT as i32 - 28, 24);
+
+            canvas.present();
+            sleep(Duration::new(0, 1_000_000u32) / 60);
+        }
+    }
+
+    // the player gets the controls back when a bot stops answering
+    let mut bot = options.bot.as_ref().and_then(|command| launch_bot(command, "playing", options.bot_timeout));
+    let mut advisor = options.advisor.as_ref()
+        .and_then(|command| launch_bot(command, "advising", options.bot_timeout));
+    let assisted = bot.is_some() || advisor.is_some();
+
+    loop {
+        // The engine runs at a fixed tick rate, the timers are derived from its tick count
+        while last_tick.elapsed() >= tick_duration && !tetris.is_finished() {
+            last_tick += tick_duration;
+            if let Some(Err(e)) = bot.as_mut().map(|bot| bot.play(&mut tetris)) {
+                println!("The bot stopped: {}, you have the controls back.", e);
+                bot = None;
+            }
+            tetris.tick();
+            if let Some((ref mut opponent, ref mut ai)) = versus {
+                ai.update(opponent);
+                opponent.tick();
+                opponent.receive_attack(&tetris);
+                tetris.receive_attack(opponent);
+            }
         }
 
-        canvas.set_draw_color(Color::RGB(255, 0, 0));
-        canvas.clear();
-        canvas.copy(&border, None,
-                    Rect::new((WIDTH - TETRIS_HEIGHT as u32 * 10) as i32 / 2 - 10,
-                              (HEIGHT - TETRIS_HEIGHT as u32 * 16) as i32 / 2 - 10,
-                              TETRIS_HEIGHT as u32 * 10 + 20, TETRIS_HEIGHT as u32 * 16 + 20))
-            .expect("Couldn't copy texture into window");
-        canvas.copy(&grid, None,
-                    Rect::new((WIDTH - TETRIS_HEIGHT as u32 * 10) as i32 / 2,
-                              (HEIGHT - TETRIS_HEIGHT as u32 * 16) as i32 / 2,
-                              TETRIS_HEIGHT as u32 * 10, TETRIS_HEIGHT as u32 * 16))
-            .expect("Couldn't copy texture into window");
-
-        // Drawing tetris
-        if tetris.current_piece.is_none() {
-            let current_piece = tetris.create_new_tetrimino();
-            if !current_piece.test_current_position(&tetris.game_map) {
-                print_game_info(&mut tetris);
-                break;
+        if tetris.is_finished() || tetris.is_game_over() {
+            if options.reveal {
+                reveal_stack(&mut canvas, &border, &grid, &mut textures, &mut tetris, grid_x, grid_y);
             }
-            tetris.current_piece = Some(current_piece);
+            print_result(&mut tetris, &profile, assisted);
+            break;
         }
+
+        draw_board(&mut canvas, &border, &grid, &mut textures, &tetris, grid_x, grid_y);
+
         let mut quit = false;
-        if !handle_events(&mut tetris, &mut quit, &mut timer, &mut event_pump) {
-            if let Some(ref mut piece) = tetris.current_piece {
-                for (line_nb, line) in piece.states[piece.current_state as usize]
-                    .iter().enumerate() {
-                    for (case_nb, case) in line.iter().enumerate() {
-                        if *case == 0 {
-                            continue;
-                        }
-                        canvas.copy(&textures[*case as usize - 1], None,
-                                    Rect::new(grid_x + (piece.x + case_nb as isize) as i32 * TETRIS_HEIGHT as i32,
-                                              grid_y + (piece.y + line_nb) as i32 * TETRIS_HEIGHT as i32,
-                                              TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32))
-                            .expect("Couldn't copy texture into window");
-                    }
-                }
+        if !handle_events(&mut tetris, &mut quit, &mut event_pump, &profile, &mut auto_shift) {
+            draw_piece(&mut canvas, &textures, &tetris, grid_x, grid_y);
+        }
+        let suggestion = match advisor.as_mut().map(|advisor| advisor.suggestion(&tetris)) {
+            Some(Ok(suggestion)) => suggestion,
+            Some(Err(e)) => {
+                println!("The advisor stopped: {}", e);
+                advisor = None;
+                None
             }
+            None => None,
+        };
+        if let Some(ref suggestion) = suggestion {
+            draw_suggestion(&mut canvas, &tetris, suggestion, grid_x, grid_y);
         }
 
         if quit {
-            print_game_info(&mut tetris);
+            match tetris.mode {
+                // the bots can't be saved with the game
+                _ if assisted => print_result(&mut tetris, &profile, assisted),
+                // Zen sessions are suspended on their own, and the opponent can't be saved
+                GameMode::Zen | GameMode::Versus { .. } => print_game_info(&mut tetris, &profile),
+                _ if save_game(&tetris) =>
+                    println!("Game saved, choose Continue on the title screen to resume it."),
+                _ => {
+                    println!("Couldn't save the game...");
+                    print_game_info(&mut tetris, &profile);
+                }
+            }
             break;
         }
 
-        for (line_nb, line) in tetris.game_map.iter().enumerate() {
-            for (case_nb, case) in line.iter().enumerate() {
-                if *case == 0 {
-                    continue;
-                }
-
-                canvas.copy(&textures[*case as usize - 1],
-                            None, Rect::new(grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
-                                            grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
-                                            TETRIS_HEIGHT as u32, TETRIS_HEIGHT as u32))
-                    .expect("Couldn't copy texture into window");
-            }
+        draw_hud(&mut canvas, &texture_creator, &font, &tetris, grid_x);
+        if let Some((ref opponent, _)) = versus {
+            draw_opponent(&mut canvas, &texture_creator, &font, &textures, opponent,
+                          grid_x + TETRIS_HEIGHT as i32 * 10 + 15, grid_y);
         }
 
         canvas.present();
@@ -0,0 +1,116 @@
+use serde::{Serialize, Deserialize};
+
+use crate::game_mode::frames_to_millis;
+use crate::tetris_struct::{Speed, GRAVITY_UNIT};
+
+pub const MASTER_MAX_LEVEL: u32 = 999;
+
+// (level, gravity in 1/256 G) as in TGM, the gravity applies from the given level
+const GRAVITY_TABLE: [(u32, u32); 30] = [
+    (0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48), (90, 64),
+    (100, 80), (120, 96), (140, 112), (160, 128), (170, 144), (200, 4), (220, 32), (230, 64),
+    (233, 96), (236, 128), (239, 160), (243, 192), (247, 224), (251, 256), (300, 512),
+    (330, 768), (360, 1024), (400, 1280), (420, 1024), (450, 768), (500, 5120),
+];
+
+// (level, ARE, lock delay) in frames
+const DELAY_TABLE: [(u32, u32, u32); 6] = [
+    (0, 25, 30), (500, 25, 30), (600, 16, 30), (700, 12, 30), (800, 6, 30), (900, 6, 17),
+];
+
+// (minimum score, grade)
+const GRADES: [(u32, &str); 18] = [
+    (0, "9"), (400, "8"), (800, "7"), (1400, "6"), (2000, "5"), (3500, "4"), (5500, "3"),
+    (8000, "2"), (12000, "1"), (16000, "S1"), (22000, "S2"), (30000, "S3"), (40000, "S4"),
+    (52000, "S5"), (66000, "S6"), (82000, "S7"), (100000, "S8"), (120000, "S9"),
+];
+
+// (level, minimum score, maximum time in milliseconds) to stay eligible for the GM grade
+const GM_REQUIREMENTS: [(u32, u32, u64); 3] = [
+    (300, 12000, 255_000), (500, 40000, 450_000), (MASTER_MAX_LEVEL, 126000, 810_000),
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Master {
+    pub level: u32,
+    // time in milliseconds at the end of every section of 100 levels
+    pub section_times: Vec<u64>,
+    combo: u32,
+    gm_eligible: bool,
+}
+
+impl Master {
+    pub fn new() -> Master {
+        Master {
+            level: 0,
+            section_times: Vec::new(),
+            combo: 1,
+            gm_eligible: true,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        let gravity = GRAVITY_TABLE.iter().rev()
+            .find(|(level, _)| self.level >= *level)
+            .map(|(_, gravity)| *gravity).unwrap_or(4);
+        let (_, are, lock_delay) = *DELAY_TABLE.iter().rev()
+            .find(|(level, _, _)| self.level >= *level)
+            .unwrap_or(&DELAY_TABLE[0]);
+
+        Speed {
+            gravity: gravity * GRAVITY_UNIT / 256,
+            are,
+            lock_delay,
+        }
+    }
+
+    /// The level increases with every new piece, except on the last level of a section.
+    pub fn on_piece_spawn(&mut self) {
+        if self.level % 100 != 99 && self.level < MASTER_MAX_LEVEL - 1 {
+            self.level += 1;
+        }
+    }
+
+    /// Returns the score of the placement as computed in TGM.
+    pub fn on_piece_locked(&mut self, nb_lines: u32, all_clear: bool, soft_drop_rows: u32,
+                           score: u32, frame: u64) -> u32 {
+        if nb_lines == 0 {
+            self.combo = 1;
+            return 0;
+        }
+
+        self.combo += 2 * nb_lines - 2;
+        let bravo = if all_clear { 4 } else { 1 };
+        let added = ((self.level + nb_lines).div_ceil(4) + soft_drop_rows) * nb_lines * self.combo * bravo;
+
+        self.level = (self.level + nb_lines).min(MASTER_MAX_LEVEL);
+        let millis = frames_to_millis(frame);
+        while self.section_times.len() < (self.level / 100) as usize {
+            self.section_times.push(millis);
+        }
+        if self.level == MASTER_MAX_LEVEL && self.section_times.len() < 10 {
+            self.section_times.push(millis);
+        }
+
+        for &(level, min_score, max_time) in GM_REQUIREMENTS.iter() {
+            if self.level >= level && self.level - nb_lines < level &&
+                (score + added < min_score || millis > max_time) {
+                self.gm_eligible = false;
+            }
+        }
+        added
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.level >= MASTER_MAX_LEVEL
+    }
+
+    pub fn grade(&self, score: u32) -> &'static str {
+        if self.is_complete() && self.gm_eligible {
+            return "GM";
+        }
+        GRADES.iter().rev()
+            .find(|(min_score, _)| score >= *min_score)
+            .map(|(_, grade)| *grade).unwrap_or("9")
+    }
+}
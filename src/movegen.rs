@@ -0,0 +1,225 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::replay::Input;
+use crate::tetrimino::Tetrimino;
+
+/// A position where a piece can be locked, and the inputs taking it there from where it spawned.
+#[derive(Clone)]
+pub struct Placement {
+    pub x: isize,
+    pub y: usize,
+    pub state: u8,
+    // sorted coordinates of the cases taken by the piece
+    pub cells: Vec<(usize, usize)>,
+    // the last one is always a hard drop
+    pub inputs: Vec<Input>,
+    pub t_spin: bool,
+}
+
+/// A position of the piece during the search, with how it was reached.
+#[derive(Clone, Copy)]
+struct Node {
+    x: isize,
+    y: usize,
+    state: u8,
+    rotated: bool,
+    // index of the node it was reached from, and with which input
+    parent: Option<(usize, Input)>,
+}
+
+/// Every position where the piece can be locked from where it is, with the moves of the game:
+/// moving sideways, rotating and soft dropping, then hard dropping it. Spins and tucks under
+/// overhangs are included. Placements leaving the same map are only kept once, with the fewest
+/// inputs, apart from a T-spin and a placement without one which don't score the same.
+pub fn placements(game_map: &[Vec<u8>], spawn: &Tetrimino) -> Vec<Placement> {
+    let mut placements = Vec::new();
+    if game_map.is_empty() || !spawn.test_current_position(game_map) {
+        return placements;
+    }
+
+    // offsets of the cases taken by the piece in every state, sorted, to test the positions
+    // faster than with the whole states
+    let minos = spawn.states.iter()
+        .map(|state| {
+            let mut minos = state.iter().enumerate()
+                .flat_map(|(y, line)| line.iter().enumerate()
+                    .filter(|(_, case)| **case != 0)
+                    .map(move |(x, _)| (x as isize, y)))
+                .collect::<Vec<_>>();
+            minos.sort();
+            minos
+        })
+        .collect::<Vec<_>>();
+    let fits = |state: u8, x: isize, y: usize| {
+        minos[state as usize].iter().all(|&(shift_x, shift_y)| {
+            let (x, y) = (x + shift_x, y + shift_y);
+            x >= 0 && y < game_map.len() && (x as usize) < game_map[y].len() && game_map[y][x as usize] == 0
+        })
+    };
+
+    // positions seen, by rotation state, rotated flag, line and column, the piece being allowed
+    // to stick out of the map on the left by the width of its states
+    let size = spawn.states.iter()
+        .map(|state| state.iter().map(Vec::len).max().unwrap_or(0))
+        .max().unwrap_or(0);
+    let (width, height) = (game_map[0].len() + 2 * size, game_map.len());
+    let mut seen = vec![false; spawn.states.len() * 2 * height * width];
+    let index = |node: &Node| {
+        (((node.state as usize * 2 + node.rotated as usize) * height + node.y) * width) +
+            (node.x + size as isize) as usize
+    };
+    // only the T-spins care about the piece having been rotated last
+    let is_t = spawn.name == 'T';
+    let step = spawn.step as isize;
+    let mut results = HashSet::new();
+    let mut nodes = Vec::new();
+    let mut queue = VecDeque::new();
+    // the piece rotated and checked for T-spins, to use the same rules as the game
+    let mut piece = spawn.clone();
+
+    let first = Node { x: spawn.x, y: spawn.y, state: spawn.current_state, rotated: is_t && spawn.rotated,
+                       parent: None };
+    seen[index(&first)] = true;
+    nodes.push(first);
+    queue.push_back(0);
+
+    while let Some(current) = queue.pop_front() {
+        let Node { x, y, state, rotated, parent } = nodes[current];
+
+        // a piece soft dropped lands where it would have from above, with fewer inputs
+        if !matches!(parent, Some((_, Input::SoftDrop))) {
+            let mut bottom = y;
+            while fits(state, x, bottom + spawn.step) {
+                bottom += spawn.step;
+            }
+            let cells = minos[state as usize].iter()
+                .map(|&(shift_x, shift_y)| ((x + shift_x) as usize, bottom + shift_y))
+                .collect::<Vec<_>>();
+            // falling after the rotation loses the T-spin
+            place(&mut piece, &Node { x, y: bottom, state, rotated: rotated && bottom == y, parent });
+            let t_spin = piece.is_t_spin(game_map);
+            if results.insert((cells.clone(), t_spin)) {
+                let mut inputs = vec![Input::HardDrop];
+                let mut node = &nodes[current];
+                while let Some((parent, input)) = node.parent {
+                    inputs.push(input);
+                    node = &nodes[parent];
+                }
+                inputs.reverse();
+                placements.push(Placement { x, y: bottom, state, cells, inputs, t_spin });
+            }
+        }
+
+        for input in [Input::Left, Input::Right, Input::Rotate, Input::SoftDrop] {
+            let moved = match input {
+                Input::Left if fits(state, x - step, y) => (x - step, y, state, false),
+                Input::Right if fits(state, x + step, y) => (x + step, y, state, false),
+                Input::Rotate => {
+                    place(&mut piece, &nodes[current]);
+                    piece.rotate(game_map);
+                    if piece.current_state == state {
+                        continue;
+                    }
+                    (piece.x, piece.y, piece.current_state, is_t)
+                }
+                Input::SoftDrop if fits(state, x, y + spawn.step) => (x, y + spawn.step, state, false),
+                _ => continue,
+            };
+            let (x, y, state, rotated) = moved;
+            let node = Node { x, y, state, rotated, parent: Some((current, input)) };
+            let index = index(&node);
+            if !seen[index] {
+                seen[index] = true;
+                nodes.push(node);
+                queue.push_back(nodes.len() - 1);
+            }
+        }
+    }
+    placements
+}
+
+fn place(piece: &mut Tetrimino, node: &Node) {
+    piece.x = node.x;
+    piece.y = node.y;
+    piece.current_state = node.state;
+    piece.rotated = node.rotated;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetrimino::{TetriminoGenerator, TetriminoI, TetriminoO, TetriminoT};
+    use crate::tetris_struct::{GARBAGE, MAP_HEIGHT, MAP_WIDTH};
+
+    /// An empty map with the given lines of garbage at its bottom, # being a full case.
+    fn map(lines: &[&str]) -> Vec<Vec<u8>> {
+        let mut game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT - lines.len()];
+        game_map.extend(lines.iter()
+            .map(|line| line.chars().map(|case| if case == '#' { GARBAGE } else { 0 }).collect::<Vec<_>>()));
+        game_map
+    }
+
+    // the T slides under the roof, then stands up in the slot, kicked to the right
+    const TSD: &[&str] = &["......####", "..........", "###.....##", "######..##", "#######.##"];
+
+    #[test]
+    fn the_t_spins_into_a_slot() {
+        let game_map = map(TSD);
+        let slot = vec![(6, 14), (7, 13), (7, 14), (7, 15)];
+        let placements = placements(&game_map, &TetriminoT::new());
+
+        let spin = placements.iter().find(|placement| placement.cells == slot && placement.t_spin).unwrap();
+        assert!(spin.inputs.iter().rev().nth(1) == Some(&Input::Rotate));
+        // dropped after being stood up, the T fills the same cases without a spin
+        let drop = placements.iter().find(|placement| placement.cells == slot && !placement.t_spin).unwrap();
+        assert!(drop.inputs.len() < spin.inputs.len());
+
+        // the spin is only a spin where it's locked
+        let mut piece = TetriminoT::new();
+        for input in spin.inputs.iter().take(spin.inputs.len() - 1) {
+            match input {
+                Input::Left => { piece.change_position(&game_map, piece.x - 1, piece.y); }
+                Input::Right => { piece.change_position(&game_map, piece.x + 1, piece.y); }
+                Input::Rotate => piece.rotate(&game_map),
+                Input::SoftDrop => { piece.change_position(&game_map, piece.x, piece.y + 1); }
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(piece.cells(), slot);
+        assert!(piece.is_t_spin(&game_map));
+    }
+
+    #[test]
+    fn pieces_are_tucked_under_overhangs() {
+        let game_map = map(TSD);
+        let placements = placements(&game_map, &TetriminoT::new());
+
+        let tuck = placements.iter().find(|placement| placement.cells == [(6, 12), (7, 12), (7, 13), (8, 12)])
+            .unwrap();
+        assert!(!tuck.t_spin);
+        // the roof is passed under after the piece went down
+        let moves = &tuck.inputs[tuck.inputs.iter().position(|input| *input == Input::SoftDrop).unwrap()..];
+        assert!(moves.contains(&Input::Right));
+        // the cases under the roof can't be reached by a hard drop alone
+        assert!(placements.iter()
+            .filter(|placement| placement.inputs.iter().all(|input| *input != Input::SoftDrop))
+            .all(|placement| placement.cells.iter().all(|&(x, y)| (0..y).all(|above| game_map[above][x] == 0))));
+    }
+
+    #[test]
+    fn placements_filling_the_same_cases_are_kept_once() {
+        let game_map = map(&[]);
+        // the two states of the I, one of the O, lying on the floor
+        for (spawn, count) in [(TetriminoI::new(), 7 + 10), (TetriminoO::new(), 9)] {
+            let placements = placements(&game_map, &spawn);
+            assert_eq!(placements.len(), count);
+            let cells = placements.iter().map(|placement| placement.cells.clone()).collect::<HashSet<_>>();
+            assert_eq!(cells.len(), count);
+        }
+
+        let placements = placements(&map(TSD), &TetriminoT::new());
+        let cells = placements.iter().map(|placement| (placement.cells.clone(), placement.t_spin))
+            .collect::<HashSet<_>>();
+        assert_eq!(cells.len(), placements.len());
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tetrimino::{DEFAULT_KICKS, Tetrimino, TetriminoJ, TetriminoI, TetriminoT, TetriminoZ, TetriminoS, TetriminoO, TetriminoL, TetriminoGenerator};
+use crate::tetris_struct::{GARBAGE, MAP_WIDTH};
+
+pub const MAX_PIECE_SIZE: usize = 5;
+const MINO: char = '#';
+
+const STANDARD_COLORS: [(u8, u8, u8); 7] = [(255, 69, 69), (255, 220, 69), (237, 150, 37),
+    (171, 99, 237), (77, 149, 239), (39, 218, 225), (45, 216, 47)];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PieceDefinition {
+    pub name: char,
+    pub color: (u8, u8, u8),
+    // every rotation state of the piece, as lines in which MINO marks a mino
+    pub states: Vec<Vec<String>>,
+    // the piece is centered on the map by default
+    #[serde(default)]
+    pub spawn_x: Option<isize>,
+    #[serde(default)]
+    pub spawn_y: usize,
+    // replaces the kicks of the piece set for this piece
+    #[serde(default)]
+    pub kicks: Option<Vec<(isize, isize)>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PieceSet {
+    pub name: String,
+    #[serde(default = "default_kicks")]
+    pub kicks: Vec<(isize, isize)>,
+    pub pieces: Vec<PieceDefinition>,
+}
+
+fn default_kicks() -> Vec<(isize, isize)> {
+    DEFAULT_KICKS.to_vec()
+}
+
+impl PieceSet {
+    /// The seven tetriminos.
+    pub fn standard() -> PieceSet {
+        let tetriminos = [TetriminoI::new(), TetriminoJ::new(), TetriminoL::new(), TetriminoO::new(),
+            TetriminoS::new(), TetriminoZ::new(), TetriminoT::new()];
+
+        PieceSet {
+            name: "standard".to_owned(),
+            kicks: default_kicks(),
+            pieces: tetriminos.iter().zip(STANDARD_COLORS.iter())
+                .map(|(tetrimino, color)| PieceDefinition {
+                    name: tetrimino.name,
+                    color: *color,
+                    states: tetrimino.states.iter()
+                        .map(|state| state.iter()
+                            .map(|line| line.iter().map(|case| if *case != 0 { MINO } else { '.' }).collect())
+                            .collect())
+                        .collect(),
+                    spawn_x: Some(tetrimino.x),
+                    spawn_y: tetrimino.y,
+                    kicks: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn from_json(content: &str) -> Result<PieceSet, String> {
+        let piece_set: PieceSet = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+        if piece_set.pieces.is_empty() || piece_set.pieces.len() >= GARBAGE as usize {
+            return Err(format!("a piece set needs between 1 and {} pieces", GARBAGE - 1));
+        }
+        for piece in piece_set.pieces.iter() {
+            if piece.states.is_empty() {
+                return Err(format!("piece {} has no rotation state", piece.name));
+            }
+            for state in piece.states.iter() {
+                if state.len() > MAX_PIECE_SIZE || state.iter().any(|line| line.chars().count() > MAX_PIECE_SIZE) {
+                    return Err(format!("piece {} is bigger than {}x{}", piece.name, MAX_PIECE_SIZE, MAX_PIECE_SIZE));
+                }
+                if !state.iter().any(|line| line.contains(MINO)) {
+                    return Err(format!("piece {} has an empty rotation state", piece.name));
+                }
+            }
+        }
+        Ok(piece_set)
+    }
+
+    pub fn colors(&self) -> Vec<(u8, u8, u8)> {
+        self.pieces.iter().map(|piece| piece.color).collect()
+    }
+
+    pub fn position(&self, name: char) -> Option<usize> {
+        self.pieces.iter().position(|piece| piece.name == name)
+    }
+
+    /// Creates the piece at `index` in the set, its minos are `index + 1` in the game map.
+    pub fn create(&self, index: usize) -> Tetrimino {
+        let piece = &self.pieces[index];
+        let case = index as u8 + 1;
+        let states = piece.states.iter()
+            .map(|state| state.iter()
+                .map(|line| line.chars().map(|c| if c == MINO { case } else { 0 }).collect())
+                .collect::<Vec<Vec<u8>>>())
+            .collect::<Vec<_>>();
+        let width = states[0].iter().map(|line| line.len()).max().unwrap_or(0);
+
+        Tetrimino {
+            name: piece.name,
+            states,
+            x: piece.spawn_x.unwrap_or((MAP_WIDTH as isize - width as isize) / 2),
+            y: piece.spawn_y,
+            current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: piece.kicks.clone().unwrap_or_else(|| self.kicks.clone()),
+        }
+    }
+}
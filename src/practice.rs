@@ -0,0 +1,370 @@
+use std::collections::HashSet;
+
+use serde::{Serialize, Deserialize};
+
+use crate::fumen;
+use crate::movegen;
+use crate::piece_set::PieceSet;
+use crate::random::Random;
+use crate::tetrimino::Tetrimino;
+use crate::tetris_struct::{MAP_WIDTH, MAP_HEIGHT};
+
+const BAG: [char; 7] = ['I', 'J', 'L', 'O', 'S', 'Z', 'T'];
+// number of bags tried to find a first bag with which the opener can be built
+const MAX_BAG_TRIES: u32 = 1000;
+
+/// An opener of the library, every fumen is a valid shape to build, with a piece placed per page.
+#[derive(Deserialize)]
+pub struct Opener {
+    pub name: String,
+    pub solutions: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct Placement {
+    piece: char,
+    // sorted coordinates of the minos
+    cells: Vec<(usize, usize)>,
+}
+
+/// Where a piece can be locked, and the pieces locked so far.
+struct Board {
+    game_map: Vec<Vec<u8>>,
+}
+
+impl Board {
+    fn new(placements: &[Placement]) -> Board {
+        let mut game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT];
+        for placement in placements.iter() {
+            for &(x, y) in placement.cells.iter() {
+                game_map[y][x] = 1;
+            }
+        }
+        Board { game_map }
+    }
+
+    /// Checks that the piece can go from the top of the map to the placement, and rest there.
+    fn can_reach(&self, piece: &Tetrimino, placement: &Placement) -> bool {
+        movegen::placements(&self.game_map, piece).iter().any(|reached| reached.cells == placement.cells)
+    }
+}
+
+/// A solution to build with a given queue of pieces.
+struct Search<'a> {
+    solution: &'a [Placement],
+    queue: &'a [char],
+    piece_set: &'a PieceSet,
+}
+
+impl Search<'_> {
+    /// Checks that the remaining pieces of the solution can be locked in place, with the pieces of
+    /// the queue from `next` and the use of hold.
+    fn can_finish(&self, placed: &[Placement], current: Option<char>, next: usize, held: Option<char>,
+                  can_hold: bool) -> bool {
+        if self.solution.iter().all(|placement| placed.contains(placement)) {
+            return true;
+        }
+        let current = match current {
+            Some(current) => current,
+            None => return false,
+        };
+
+        let board = Board::new(placed);
+        let piece = match self.piece_set.position(current) {
+            Some(index) => self.piece_set.create(index),
+            None => return false,
+        };
+        for placement in self.solution.iter().filter(|placement| placement.piece == current && !placed.contains(placement)) {
+            if board.can_reach(&piece, placement) {
+                let mut placed = placed.to_vec();
+                placed.push(placement.clone());
+                if self.can_finish(&placed, self.queue.get(next).cloned(), next + 1, held, true) {
+                    return true;
+                }
+            }
+        }
+
+        if can_hold {
+            return match held {
+                Some(held) => self.can_finish(placed, Some(held), next, Some(current), false),
+                None => self.can_finish(placed, self.queue.get(next).cloned(), next + 1, Some(current), false),
+            };
+        }
+        false
+    }
+}
+
+/// The different shapes of the piece in its states, as sorted offsets starting at (0, 0).
+fn shapes(piece: &Tetrimino) -> Vec<Vec<(usize, usize)>> {
+    let mut shapes: Vec<Vec<(usize, usize)>> = Vec::new();
+    for state in piece.states.iter() {
+        let mut cells = state.iter().enumerate()
+            .flat_map(|(y, line)| line.iter().enumerate()
+                .filter(|(_, case)| **case != 0)
+                .map(move |(x, _)| (x, y)))
+            .collect::<Vec<_>>();
+        let min_x = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+        let min_y = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+        cells = cells.iter().map(|&(x, y)| (x - min_x, y - min_y)).collect();
+        cells.sort();
+        if !shapes.contains(&cells) {
+            shapes.push(cells);
+        }
+    }
+    shapes
+}
+
+/// Cuts the sorted cells in pieces of the shapes, if they can be. The first cell left is always
+/// the first one of the piece covering it.
+fn cut(cells: &[(usize, usize)], shapes: &[Vec<(usize, usize)>]) -> Option<Vec<Vec<(usize, usize)>>> {
+    let (first_x, first_y) = match cells.first() {
+        Some(&first) => first,
+        None => return Some(Vec::new()),
+    };
+    for shape in shapes.iter() {
+        let (anchor_x, anchor_y) = shape[0];
+        let piece = shape.iter()
+            .map(|&(x, y)| (first_x + x - anchor_x, (first_y + y).checked_sub(anchor_y)))
+            .filter_map(|(x, y)| y.map(|y| (x, y)))
+            .collect::<Vec<_>>();
+        if piece.len() != shape.len() || !piece.iter().all(|cell| cells.contains(cell)) {
+            continue;
+        }
+        let rest = cells.iter().filter(|cell| !piece.contains(cell)).cloned().collect::<Vec<_>>();
+        if let Some(mut pieces) = cut(&rest, shapes) {
+            pieces.push(piece);
+            return Some(pieces);
+        }
+    }
+    None
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Practice {
+    pub opener: String,
+    solutions: Vec<Vec<Placement>>,
+    // the pieces given, and the ones to give next
+    queue: Vec<char>,
+    next: usize,
+    placed: Vec<Placement>,
+    diverged: bool,
+    complete: bool,
+}
+
+impl Practice {
+    pub fn new(opener: &Opener, piece_set: &PieceSet) -> Result<Practice, String> {
+        let mut solutions = Vec::new();
+
+        for data in opener.solutions.iter() {
+            let pages = fumen::decode(data, piece_set)?;
+            // the pieces drawn on the first page, then the ones placed on every page in order
+            let mut solution = Practice::split_pieces(&pages[0].game_map, piece_set)
+                .map_err(|e| format!("{}: {}", opener.name, e))?;
+            solution.extend(pages.iter().filter_map(|page| page.piece.as_ref())
+                .map(|piece| Placement { piece: piece.name, cells: piece.cells() }));
+            let cells = solution.iter().flat_map(|placement| placement.cells.iter()).collect::<HashSet<_>>();
+            if cells.len() != solution.iter().map(|placement| placement.cells.len()).sum::<usize>() {
+                return Err(format!("{}: the pieces of a solution overlap", opener.name));
+            }
+            solutions.push(solution);
+        }
+        if solutions.is_empty() {
+            return Err(format!("{} has no solution", opener.name));
+        }
+
+        let practice = Practice {
+            opener: opener.name.clone(),
+            solutions,
+            queue: Vec::new(),
+            next: 0,
+            placed: Vec::new(),
+            diverged: false,
+            complete: false,
+        };
+        practice.check(piece_set)?;
+        Ok(practice)
+    }
+
+    /// Splits the minos of a target shape in pieces: every group of connected minos of the same
+    /// color is cut in pieces of its shape, two pieces of the same color can touch.
+    fn split_pieces(game_map: &[Vec<u8>], piece_set: &PieceSet) -> Result<Vec<Placement>, String> {
+        let mut placements = Vec::new();
+        let mut seen = vec![vec![false; MAP_WIDTH]; MAP_HEIGHT];
+
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                let case = game_map[y][x];
+                if case == 0 || seen[y][x] {
+                    continue;
+                }
+                let index = case as usize - 1;
+                if index >= piece_set.pieces.len() {
+                    return Err("the shape can only be made of pieces".to_owned());
+                }
+                let piece = piece_set.create(index);
+
+                let mut cells = Vec::new();
+                let mut to_visit = vec![(x, y)];
+                seen[y][x] = true;
+                while let Some((x, y)) = to_visit.pop() {
+                    cells.push((x, y));
+                    let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+                    for &(nx, ny) in neighbours.iter() {
+                        if nx < MAP_WIDTH && ny < MAP_HEIGHT && !seen[ny][nx] && game_map[ny][nx] == case {
+                            seen[ny][nx] = true;
+                            to_visit.push((nx, ny));
+                        }
+                    }
+                }
+                cells.sort();
+                let pieces = match cut(&cells, &shapes(&piece)) {
+                    Some(pieces) => pieces,
+                    None => return Err(format!("the minos of piece {} can't be told apart", piece.name)),
+                };
+                placements.extend(pieces.into_iter().map(|cells| Placement { piece: piece.name, cells }));
+            }
+        }
+        Ok(placements)
+    }
+
+    fn push_bag(&mut self, random: &mut Random) {
+        let mut bag = BAG;
+        for i in (1..bag.len()).rev() {
+            bag.swap(i, random.below(i as u32 + 1) as usize);
+        }
+        self.queue.extend_from_slice(&bag);
+    }
+
+    /// Fills the queue with bags with which at least one of the solutions can be built.
+    fn create_queue(&mut self, random: &mut Random, piece_set: &PieceSet) {
+        let length = self.solutions.iter().map(|solution| solution.len()).max().unwrap_or(0) + 1;
+
+        for _ in 0..MAX_BAG_TRIES {
+            self.queue.clear();
+            while self.queue.len() < length {
+                self.push_bag(random);
+            }
+            let queue = &self.queue;
+            if self.solutions.iter().any(|solution| Search { solution, queue, piece_set }
+                .can_finish(&[], Some(queue[0]), 1, None, true)) {
+                return;
+            }
+        }
+    }
+
+    pub fn next_piece(&mut self, random: &mut Random, piece_set: &PieceSet) -> char {
+        if self.queue.is_empty() {
+            self.create_queue(random, piece_set);
+        }
+        if self.next >= self.queue.len() {
+            self.push_bag(random);
+        }
+        self.next += 1;
+        self.queue[self.next - 1]
+    }
+
+    /// The next pieces of the queue.
+    pub fn preview(&self, nb_pieces: usize) -> String {
+        self.queue.iter().skip(self.next).take(nb_pieces).collect()
+    }
+
+    /// Checks the placement against the solutions, the player diverges once none of them can be
+    /// built anymore with the queue. `cells` are in minos, not in cases.
+    pub fn on_piece_locked(&mut self, piece: char, mut cells: Vec<(usize, usize)>, cleared: u32,
+                           held: Option<char>, piece_set: &PieceSet) {
+        if self.diverged || self.complete {
+            return;
+        }
+        cells.sort();
+        self.placed.push(Placement { piece, cells });
+
+        let placed = &self.placed;
+        let alive = self.solutions.iter()
+            .filter(|solution| placed.iter().all(|placement| solution.contains(placement)))
+            .collect::<Vec<_>>();
+        self.complete = alive.iter().any(|solution| solution.len() == placed.len());
+        // the shapes are built before any line is cleared
+        let queue = &self.queue;
+        self.diverged = !self.complete && (cleared > 0 || !alive.iter().any(|solution| {
+            Search { solution, queue, piece_set }
+                .can_finish(placed, queue.get(self.next).cloned(), self.next + 1, held, true)
+        }));
+    }
+
+    pub fn is_diverged(&self) -> bool {
+        self.diverged
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn nb_placed(&self) -> usize {
+        self.placed.len()
+    }
+
+    /// Checks that every piece of the solutions is in the piece set.
+    pub fn check(&self, piece_set: &PieceSet) -> Result<(), String> {
+        match BAG.iter().find(|name| piece_set.position(**name).is_none()) {
+            Some(name) => Err(format!("piece {} isn't in the {} piece set", name, piece_set.name)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_handler::load_openers;
+    use crate::piece_set::PieceDefinition;
+
+    #[test]
+    fn every_opener_can_be_built() {
+        let piece_set = PieceSet::standard();
+        for opener in load_openers().unwrap().iter() {
+            let mut practice = Practice::new(opener, &piece_set).unwrap();
+            assert!(practice.solutions.iter().all(|solution| !solution.is_empty()));
+            for seed in 0..5 {
+                practice.create_queue(&mut Random::new(seed), &piece_set);
+                let queue = &practice.queue;
+                assert!(practice.solutions.iter().any(|solution| Search { solution, queue, piece_set: &piece_set }
+                    .can_finish(&[], Some(queue[0]), 1, None, true)), "{}", opener.name);
+            }
+        }
+    }
+
+    #[test]
+    fn pieces_of_the_same_color_are_told_apart() {
+        let mut piece_set = PieceSet::standard();
+        piece_set.pieces.push(PieceDefinition {
+            name: 'P',
+            color: (255, 255, 255),
+            states: vec![vec!["##".to_owned(), "##".to_owned(), "#.".to_owned()]],
+            spawn_x: None,
+            spawn_y: 0,
+            kicks: None,
+        });
+        let o = piece_set.position('O').unwrap() as u8 + 1;
+        let p = piece_set.position('P').unwrap() as u8 + 1;
+        let mut game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT];
+        // two O side by side, two P one over the other
+        for (x, y) in (0..4).flat_map(|x| (14..16).map(move |y| (x, y))) {
+            game_map[y][x] = o;
+        }
+        for (x, y) in [(6, 10), (7, 10), (6, 11), (7, 11), (6, 12), (6, 13), (7, 13), (6, 14), (7, 14), (6, 15)] {
+            game_map[y][x] = p;
+        }
+
+        let mut placements = Practice::split_pieces(&game_map, &piece_set).unwrap();
+        placements.sort_by_key(|placement| placement.cells[0]);
+        let cells = placements.iter().map(|placement| (placement.piece, placement.cells.clone())).collect::<Vec<_>>();
+        assert!(cells == [('O', vec![(0, 14), (0, 15), (1, 14), (1, 15)]),
+                          ('O', vec![(2, 14), (2, 15), (3, 14), (3, 15)]),
+                          ('P', vec![(6, 10), (6, 11), (6, 12), (7, 10), (7, 11)]),
+                          ('P', vec![(6, 13), (6, 14), (6, 15), (7, 13), (7, 14)])]);
+
+        // a group which isn't made of whole pieces
+        game_map[15][3] = 0;
+        assert!(Practice::split_pieces(&game_map, &piece_set).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::leaderboard::{Entry, Metric};
+use crate::replay::Input;
+
+// version of the profiles file format
+pub const PROFILES_VERSION: u32 = 1;
+// delayed auto shift and auto repeat rate, in milliseconds
+const DEFAULT_DAS: u64 = 167;
+const DEFAULT_ARR: u64 = 33;
+
+/// How the minos are colored, from the colors of the piece set.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Skin {
+    #[default]
+    Standard,
+    Pastel,
+    Monochrome,
+}
+
+impl Skin {
+    pub fn name(self) -> &'static str {
+        match self {
+            Skin::Standard => "Standard",
+            Skin::Pastel => "Pastel",
+            Skin::Monochrome => "Monochrome",
+        }
+    }
+
+    pub fn next(self) -> Skin {
+        match self {
+            Skin::Standard => Skin::Pastel,
+            Skin::Pastel => Skin::Monochrome,
+            Skin::Monochrome => Skin::Standard,
+        }
+    }
+
+    pub fn color(self, (r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+        match self {
+            Skin::Standard => (r, g, b),
+            // halfway to white
+            Skin::Pastel => ((r / 2) + 128, (g / 2) + 128, (b / 2) + 128),
+            Skin::Monochrome => {
+                let luma = ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8;
+                (luma, luma, luma)
+            }
+        }
+    }
+}
+
+/// The keys bound to every input, by their SDL names.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bindings {
+    pub left: Vec<String>,
+    pub right: Vec<String>,
+    pub rotate: Vec<String>,
+    pub soft_drop: Vec<String>,
+    pub hard_drop: Vec<String>,
+    pub hold: Vec<String>,
+    pub undo: Vec<String>,
+    pub redo: Vec<String>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        let keys = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        Bindings {
+            left: keys(&["Left"]),
+            right: keys(&["Right"]),
+            rotate: keys(&["Up"]),
+            soft_drop: keys(&["Down"]),
+            hard_drop: keys(&["Space"]),
+            hold: keys(&["C", "Left Shift"]),
+            undo: keys(&["Z"]),
+            redo: keys(&["Y"]),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn keys(&self, input: Input) -> &[String] {
+        match input {
+            Input::Left => &self.left,
+            Input::Right => &self.right,
+            Input::Rotate => &self.rotate,
+            Input::SoftDrop => &self.soft_drop,
+            Input::HardDrop => &self.hard_drop,
+            Input::Hold => &self.hold,
+            Input::Undo => &self.undo,
+            Input::Redo => &self.redo,
+        }
+    }
+
+    /// Binds the key to the input alone, in place of the keys it had.
+    pub fn bind(&mut self, input: Input, key: &str) {
+        for other in Input::ALL.iter() {
+            self.keys_mut(*other).retain(|bound| !bound.eq_ignore_ascii_case(key));
+        }
+        *self.keys_mut(input) = vec![key.to_owned()];
+    }
+
+    /// The input bound to the key, if any.
+    pub fn input(&self, key: &str) -> Option<Input> {
+        Input::ALL.iter().cloned()
+            .find(|input| self.keys(*input).iter().any(|bound| bound.eq_ignore_ascii_case(key)))
+    }
+
+    fn keys_mut(&mut self, input: Input) -> &mut Vec<String> {
+        match input {
+            Input::Left => &mut self.left,
+            Input::Right => &mut self.right,
+            Input::Rotate => &mut self.rotate,
+            Input::SoftDrop => &mut self.soft_drop,
+            Input::HardDrop => &mut self.hard_drop,
+            Input::Hold => &mut self.hold,
+            Input::Undo => &mut self.undo,
+            Input::Redo => &mut self.redo,
+        }
+    }
+}
+
+/// How the sideways moves repeat while the key is held, in milliseconds.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Handling {
+    // delay before the moves start repeating
+    pub das: u64,
+    // delay between two repeated moves, 0 to go straight to the wall
+    pub arr: u64,
+}
+
+impl Default for Handling {
+    fn default() -> Handling {
+        Handling {
+            das: DEFAULT_DAS,
+            arr: DEFAULT_ARR,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub bindings: Bindings,
+    #[serde(default)]
+    pub handling: Handling,
+    #[serde(default)]
+    pub skin: Skin,
+    // the best game of the profile in every leaderboard, by the key of the leaderboard
+    #[serde(default)]
+    pub bests: BTreeMap<String, Entry>,
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Profile {
+        Profile {
+            name: name.to_owned(),
+            bindings: Bindings::default(),
+            handling: Handling::default(),
+            skin: Skin::default(),
+            bests: BTreeMap::new(),
+        }
+    }
+
+    /// Keeps the game if it's the best one of the profile in its leaderboard. Returns true if it
+    /// is.
+    pub fn record(&mut self, key: &str, entry: Entry, metric: Metric) -> bool {
+        if let Some(best) = self.bests.get(key) {
+            if metric.compare(&entry, best) != Ordering::Less {
+                return false;
+            }
+        }
+        self.bests.insert(key.to_owned(), entry);
+        true
+    }
+}
+
+/// The local profiles, one of which is playing.
+#[derive(Serialize, Deserialize)]
+pub struct Profiles {
+    pub version: u32,
+    // index of the profile playing
+    pub current: usize,
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for Profiles {
+    fn default() -> Profiles {
+        // the first profile is named after the user of the system
+        let name = std::env::var("USER").or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "Player".to_owned());
+
+        Profiles {
+            version: PROFILES_VERSION,
+            current: 0,
+            profiles: vec![Profile::new(&name)],
+        }
+    }
+}
+
+impl Profiles {
+    pub fn current(&self) -> &Profile {
+        &self.profiles[self.current.min(self.profiles.len() - 1)]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Profile {
+        let current = self.current.min(self.profiles.len() - 1);
+        &mut self.profiles[current]
+    }
+
+    /// Adds a profile and selects it, or only selects it if there's already one with that name.
+    pub fn select_or_add(&mut self, name: &str) {
+        self.current = match self.profiles.iter().position(|profile| profile.name == name) {
+            Some(index) => index,
+            None => {
+                self.profiles.push(Profile::new(name));
+                self.profiles.len() - 1
+            }
+        };
+    }
+}
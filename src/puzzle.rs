@@ -0,0 +1,114 @@
+use serde::{Serialize, Deserialize};
+
+use crate::piece_set::PieceSet;
+use crate::tetris_struct::{MAP_WIDTH, MAP_HEIGHT};
+
+// an empty case in the map of a puzzle, the other characters are either the name of a piece for
+// a mino of its color, or garbage
+pub const EMPTY: char = '.';
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Goal {
+    ClearLines { lines: u32 },
+    TSpinDouble,
+    AllClear { pieces: u32 },
+}
+
+impl Goal {
+    pub fn description(&self) -> String {
+        match *self {
+            Goal::ClearLines { lines } => format!("Clear {} lines", lines),
+            Goal::TSpinDouble => "Perform a T-spin double".to_owned(),
+            Goal::AllClear { pieces } => format!("Clear the whole map in {} pieces", pieces),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Puzzle {
+    pub name: String,
+    // name of the file of the pack the puzzle comes from
+    #[serde(default)]
+    pub pack: String,
+    // lines of the starting map, from the top, the last one being the bottom of the map
+    #[serde(default)]
+    pub map: Vec<String>,
+    // names of the pieces, in the order they are given
+    pub queue: String,
+    pub goal: Goal,
+    #[serde(default)]
+    pieces_used: usize,
+    #[serde(default)]
+    solved: bool,
+}
+
+impl Puzzle {
+    /// Name of the next piece of the queue, None when the queue is over or when the puzzle can't
+    /// be solved with more pieces.
+    pub fn next_piece(&mut self) -> Option<char> {
+        if let Goal::AllClear { pieces } = self.goal {
+            if self.pieces_used >= pieces as usize {
+                return None;
+            }
+        }
+        let piece = self.queue.chars().nth(self.pieces_used)?;
+        self.pieces_used += 1;
+        Some(piece)
+    }
+
+    /// Pieces which haven't been given yet.
+    pub fn remaining(&self) -> String {
+        self.queue.chars().skip(self.pieces_used).collect()
+    }
+
+    pub fn on_piece_locked(&mut self, nb_lines: u32, cleared: u32, t_spin: bool, all_clear: bool) {
+        self.solved |= match self.goal {
+            Goal::ClearLines { lines } => nb_lines >= lines,
+            Goal::TSpinDouble => t_spin && cleared == 2,
+            Goal::AllClear { pieces } => all_clear && self.pieces_used <= pieces as usize,
+        };
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.solved
+    }
+
+    /// Identifies the puzzle among all the packs.
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.pack, self.name)
+    }
+
+    /// Checks that every piece of the queue is in the piece set.
+    pub fn check(&self, piece_set: &PieceSet) -> Result<(), String> {
+        match self.queue.chars().find(|name| piece_set.position(*name).is_none()) {
+            Some(name) => Err(format!("puzzle {}: piece {} isn't in the {} piece set", self.name, name,
+                                      piece_set.name)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PuzzlePack {
+    pub name: String,
+    pub puzzles: Vec<Puzzle>,
+}
+
+impl PuzzlePack {
+    pub fn from_json(content: &str, file_name: &str) -> Result<PuzzlePack, String> {
+        let mut pack: PuzzlePack = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+        for puzzle in pack.puzzles.iter_mut() {
+            if puzzle.map.len() > MAP_HEIGHT || puzzle.map.iter().any(|line| line.chars().count() > MAP_WIDTH) {
+                return Err(format!("the map of puzzle {} is bigger than {}x{}", puzzle.name, MAP_WIDTH,
+                                   MAP_HEIGHT));
+            }
+            if puzzle.queue.is_empty() {
+                return Err(format!("puzzle {} has no piece", puzzle.name));
+            }
+            puzzle.pack = file_name.to_owned();
+        }
+        Ok(pack)
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Serialize, Deserialize};
+
+/// Small seedable random generator (SplitMix64), so the game can be reproduced from its seed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..max`.
+    pub fn below(&mut self, max: u32) -> u32 {
+        (((self.next_u64() >> 32) * max as u64) >> 32) as u32
+    }
+}
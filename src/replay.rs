@@ -0,0 +1,242 @@
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use crate::game_mode::GameMode;
+use crate::rules::Rules;
+use crate::tetris_struct::Tetris;
+
+// version of the replay format, to bump whenever the simulation or the inputs change
+pub const REPLAY_VERSION: u32 = 2;
+// ticks between two keyframes, the playback starts from the closest one to seek
+const KEYFRAME_TICKS: u64 = 600;
+
+/// An action of the player, applied to the game between two ticks.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Input {
+    Left,
+    Right,
+    Rotate,
+    SoftDrop,
+    HardDrop,
+    Hold,
+    Undo,
+    Redo,
+}
+
+impl Input {
+    pub const ALL: [Input; 8] = [Input::Left, Input::Right, Input::Rotate, Input::SoftDrop, Input::HardDrop,
+                                 Input::Hold, Input::Undo, Input::Redo];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Input::Left => "Move left",
+            Input::Right => "Move right",
+            Input::Rotate => "Rotate",
+            Input::SoftDrop => "Soft drop",
+            Input::HardDrop => "Hard drop",
+            Input::Hold => "Hold",
+            Input::Undo => "Undo",
+            Input::Redo => "Redo",
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            Input::Left => 'L',
+            Input::Right => 'R',
+            Input::Rotate => 'C',
+            Input::SoftDrop => 'S',
+            Input::HardDrop => 'D',
+            Input::Hold => 'H',
+            Input::Undo => 'Z',
+            Input::Redo => 'Y',
+        }
+    }
+
+    fn from_char(c: char) -> Option<Input> {
+        match c {
+            'L' => Some(Input::Left),
+            'R' => Some(Input::Right),
+            'C' => Some(Input::Rotate),
+            'S' => Some(Input::SoftDrop),
+            'D' => Some(Input::HardDrop),
+            'H' => Some(Input::Hold),
+            'Z' => Some(Input::Undo),
+            'Y' => Some(Input::Redo),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the inputs as the number of ticks since the previous input followed by the input, the
+/// number being left out when it's 0. The repetitions of a held key are written in lowercase.
+fn encode_inputs(inputs: &[(u64, Input, bool)]) -> String {
+    let mut data = String::new();
+    let mut previous = 0;
+
+    for &(tick, input, repeat) in inputs.iter() {
+        if tick > previous {
+            data.push_str(&(tick - previous).to_string());
+        }
+        data.push(if repeat { input.to_char().to_ascii_lowercase() } else { input.to_char() });
+        previous = tick;
+    }
+    data
+}
+
+fn decode_inputs(data: &str) -> Result<Vec<(u64, Input, bool)>, String> {
+    let mut inputs = Vec::new();
+    let mut tick = 0u64;
+    let mut delay = 0u64;
+
+    for c in data.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            delay = delay.checked_mul(10).and_then(|delay| delay.checked_add(digit as u64))
+                .ok_or_else(|| "delay between two inputs too long".to_owned())?;
+            continue;
+        }
+        let input = Input::from_char(c.to_ascii_uppercase()).ok_or_else(|| format!("unknown input '{}'", c))?;
+        tick += delay;
+        delay = 0;
+        inputs.push((tick, input, c.is_ascii_lowercase()));
+    }
+    Ok(inputs)
+}
+
+fn serialize_inputs<S: Serializer>(inputs: &[(u64, Input, bool)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode_inputs(inputs))
+}
+
+fn deserialize_inputs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(u64, Input, bool)>, D::Error> {
+    let data = String::deserialize(deserializer)?;
+    decode_inputs(&data).map_err(serde::de::Error::custom)
+}
+
+/// Everything needed to play a game again: the game is simulated from the same seed, rules and
+/// mode, with the inputs of the player at the same ticks.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub rules: Rules,
+    // the mode as it was when the game started
+    pub mode: GameMode,
+    // the starting map, when it was replaced
+    pub game_map: Option<Vec<Vec<u8>>>,
+    // the tick of every input, and whether it's the repetition of a held key
+    #[serde(serialize_with = "serialize_inputs", deserialize_with = "deserialize_inputs")]
+    pub inputs: Vec<(u64, Input, bool)>,
+    // number of ticks of the game
+    pub length: u64,
+}
+
+impl Replay {
+    pub fn new(seed: u64, rules: Rules, mode: GameMode) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            rules,
+            mode,
+            game_map: None,
+            inputs: Vec::new(),
+            length: 0,
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, input: Input, repeat: bool) {
+        self.inputs.push((tick, input, repeat));
+    }
+}
+
+/// Plays a replay back, tick by tick, and seeks in it from the keyframes met so far.
+pub struct Player {
+    replay: Replay,
+    // index of the next input to play
+    next_input: usize,
+    // the game every KEYFRAME_TICKS ticks, before the inputs of that tick, with its next input
+    keyframes: Vec<(Tetris, usize)>,
+}
+
+impl Player {
+    pub fn new(replay: Replay) -> Result<Player, String> {
+        if replay.version != REPLAY_VERSION {
+            return Err(format!("version {} isn't supported, expected version {}", replay.version,
+                               REPLAY_VERSION));
+        }
+        let mut tetris = Tetris::with_seed(replay.mode.clone(), replay.rules.clone(), replay.seed);
+        if let Some(ref game_map) = replay.game_map {
+            tetris.set_game_map(game_map);
+        }
+
+        Ok(Player {
+            replay,
+            next_input: 0,
+            keyframes: vec![(tetris, 0)],
+        })
+    }
+
+    /// Plays the whole replay, to check the result of the game.
+    pub fn play_through(replay: Replay) -> Result<Tetris, String> {
+        let mut player = Player::new(replay)?;
+        let mut tetris = player.start();
+        player.seek(&mut tetris, player.length());
+        Ok(tetris)
+    }
+
+    /// The game as it was when it started.
+    pub fn start(&mut self) -> Tetris {
+        self.next_input = 0;
+        self.keyframes[0].0.clone()
+    }
+
+    pub fn length(&self) -> u64 {
+        self.replay.length
+    }
+
+    pub fn is_over(&self, tetris: &Tetris) -> bool {
+        tetris.is_game_over() ||
+            (tetris.frame >= self.replay.length && self.next_input >= self.replay.inputs.len())
+    }
+
+    /// Plays the inputs of the current tick, then advances the game by one tick unless it's the
+    /// last one.
+    pub fn step(&mut self, tetris: &mut Tetris) {
+        if self.is_over(tetris) {
+            return;
+        }
+        while let Some(&(tick, input, repeat)) = self.replay.inputs.get(self.next_input) {
+            if tick > tetris.frame {
+                break;
+            }
+            tetris.play(input, repeat);
+            self.next_input += 1;
+        }
+        if tetris.frame >= self.replay.length {
+            return;
+        }
+        tetris.tick();
+
+        if tetris.frame == self.keyframes.len() as u64 * KEYFRAME_TICKS {
+            self.keyframes.push((tetris.clone(), self.next_input));
+        }
+    }
+
+    /// Goes to the given tick, from the closest keyframe unless it's ahead of the game.
+    pub fn seek(&mut self, tetris: &mut Tetris, tick: u64) {
+        let tick = tick.min(self.replay.length);
+        let keyframe = ((tick / KEYFRAME_TICKS) as usize).min(self.keyframes.len() - 1);
+        let keyframe_tick = keyframe as u64 * KEYFRAME_TICKS;
+
+        if tick < tetris.frame || keyframe_tick > tetris.frame {
+            let (ref state, next_input) = self.keyframes[keyframe];
+            *tetris = state.clone();
+            self.next_input = next_input;
+        }
+        while tetris.frame < tick && !self.is_over(tetris) {
+            self.step(tetris);
+        }
+        // the inputs of the last tick are played along with it
+        if tick == self.replay.length {
+            self.step(tetris);
+        }
+    }
+}
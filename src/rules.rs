@@ -0,0 +1,65 @@
+use serde::{Serialize, Deserialize};
+
+use crate::piece_set::PieceSet;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StackVisibility {
+    Visible,
+    // the locked minos fade out `delay` ticks after being locked
+    Fading { delay: u32 },
+    Invisible,
+}
+
+/// Variations of the game rules which can be combined with any game mode.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rules {
+    pub stack_visibility: StackVisibility,
+    // every mino is 2x2 cases big, on a map twice as wide and as high
+    pub big: bool,
+    // after a line clear, the groups of connected minos fall until they rest on something
+    pub cascade: bool,
+    pub piece_set: PieceSet,
+    // the current piece can be put aside to be played later
+    pub hold: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            stack_visibility: StackVisibility::Visible,
+            big: false,
+            cascade: false,
+            piece_set: PieceSet::standard(),
+            hold: false,
+        }
+    }
+}
+
+impl Rules {
+    /// Number of cases per mino side.
+    pub fn scale(&self) -> usize {
+        if self.big { 2 } else { 1 }
+    }
+
+    /// Short name of the rules for the leaderboards, games are only ranked against games played
+    /// with the same rules.
+    pub fn key(&self) -> String {
+        let mut key = self.piece_set.name.clone();
+
+        match self.stack_visibility {
+            StackVisibility::Visible => {}
+            StackVisibility::Fading { delay } => key.push_str(&format!("+fading{}", delay)),
+            StackVisibility::Invisible => key.push_str("+invisible"),
+        }
+        if self.big {
+            key.push_str("+big");
+        }
+        if self.cascade {
+            key.push_str("+cascade");
+        }
+        if self.hold {
+            key.push_str("+hold");
+        }
+        key
+    }
+}
@@ -0,0 +1,675 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::fumen::{self, LEFT, REVERSE, RIGHT, SPAWN};
+use crate::movegen;
+use crate::replay::Input;
+use crate::tetrimino::Tetrimino;
+use crate::tetris_struct::Tetris;
+
+// number of lines of the board sent to the bots, the ones above the map are empty
+const BOARD_HEIGHT: usize = 40;
+// pieces of the preview sent after the current one
+const PREVIEW_PIECES: usize = 5;
+// letters of the tetriminos, by their value in the fumens minus one
+const LETTERS: [char; 7] = ['I', 'L', 'O', 'Z', 'T', 'J', 'S'];
+const GARBAGE_LETTER: char = 'G';
+// time given to a bot to quit before it's killed
+const QUIT_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Orientation {
+    fn rotation(self) -> u8 {
+        match self {
+            Orientation::North => SPAWN,
+            Orientation::East => RIGHT,
+            Orientation::South => REVERSE,
+            Orientation::West => LEFT,
+        }
+    }
+
+    fn of(rotation: u8) -> Orientation {
+        match rotation {
+            RIGHT => Orientation::East,
+            REVERSE => Orientation::South,
+            LEFT => Orientation::West,
+            _ => Orientation::North,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+/// Where a piece is, by the position of its center with y going up from the bottom line.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Location {
+    #[serde(rename = "type")]
+    pub piece: char,
+    pub orientation: Orientation,
+    pub x: isize,
+    pub y: isize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Move {
+    pub location: Location,
+    pub spin: Spin,
+}
+
+/// A message sent to the bot.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Rules,
+    Start {
+        hold: Option<char>,
+        // the current piece comes first
+        queue: Vec<char>,
+        combo: u32,
+        back_to_back: bool,
+        // from the bottom line, None for an empty case
+        board: Vec<Vec<Option<char>>>,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        played: Move,
+    },
+    NewPiece {
+        piece: char,
+    },
+    Stop,
+    Quit,
+}
+
+/// A message received from the bot, the ones the game doesn't know are ignored.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/// The game as the bot knows it.
+#[derive(PartialEq)]
+struct Model {
+    // true for the cases taken, from the top line as in the game map
+    board: Vec<Vec<bool>>,
+    hold: Option<char>,
+    queue: Vec<char>,
+}
+
+/// The placement suggested by the bot for a piece.
+struct Suggestion {
+    // number of pieces made permanent when it was suggested, it's only valid for that piece
+    nb_pieces: u32,
+    // the piece placed is the held one, or the next one if no piece was held
+    hold: bool,
+    target: Tetrimino,
+}
+
+/// A piece the bot can place.
+struct Candidate {
+    // the piece is the held one, or the next one if no piece was held
+    hold: bool,
+    letter: char,
+    // the cases it can take
+    reachable: Vec<Vec<(usize, usize)>>,
+}
+
+/// A bot playing or advising the player, through the Tetris Bot Protocol.
+pub struct Bot {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    // the process of the bot, if it was launched by the game
+    child: Option<Child>,
+    input: Box<dyn Write + Send>,
+    responses: Receiver<Result<Response, String>>,
+    // how long the bot can think before it's considered stuck
+    timeout: Duration,
+    model: Option<Model>,
+    // number of pieces made permanent when a suggestion was asked, and when it was asked
+    asked: Option<(u32, Instant)>,
+    suggestion: Option<Suggestion>,
+}
+
+impl Bot {
+    /// Launches the bot with the command, its program followed by its arguments, and waits for it
+    /// to be ready.
+    pub fn launch(command: &str, timeout: Duration) -> Result<Bot, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("no command to launch the bot")?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{}: {}", program, e))?;
+
+        let (input, output) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(input), Some(output)) => (input, output),
+            _ => return Err(format!("{}: couldn't talk to the bot", program)),
+        };
+        Bot::connect(Some(child), input, output, timeout)
+    }
+
+    /// Reads the messages of the bot in a thread of their own, then agrees with it on the rules.
+    fn connect(child: Option<Child>, input: impl Write + Send + 'static, output: impl Read + Send + 'static,
+               timeout: Duration) -> Result<Bot, String> {
+        let (sender, responses) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(output).lines() {
+                let response = match line {
+                    Ok(ref line) if line.trim().is_empty() => continue,
+                    Ok(line) => serde_json::from_str(&line)
+                        .map_err(|e| format!("invalid message from the bot: {}", e)),
+                    Err(e) => Err(format!("couldn't read from the bot: {}", e)),
+                };
+                if sender.send(response).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(Err("the bot exited".to_owned()));
+        });
+
+        let mut bot = Bot {
+            name: String::new(),
+            version: String::new(),
+            author: String::new(),
+            child,
+            input: Box::new(input),
+            responses,
+            timeout,
+            model: None,
+            asked: None,
+            suggestion: None,
+        };
+        match bot.receive()? {
+            Response::Info { name, version, author } => {
+                bot.name = name;
+                bot.version = version;
+                bot.author = author;
+            }
+            _ => return Err("the bot didn't introduce itself".to_owned()),
+        }
+        bot.send(&Request::Rules)?;
+        match bot.receive()? {
+            Response::Ready => Ok(bot),
+            _ => Err("the bot isn't ready".to_owned()),
+        }
+    }
+
+    fn send(&mut self, request: &Request) -> Result<(), String> {
+        let message = serde_json::to_string(request).map_err(|e| e.to_string())?;
+        writeln!(self.input, "{}", message)
+            .and_then(|_| self.input.flush())
+            .map_err(|e| format!("couldn't write to the bot: {}", e))
+    }
+
+    /// Waits for the next message of the bot, up to the timeout.
+    fn receive(&mut self) -> Result<Response, String> {
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let response = match self.responses.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(response) => response?,
+                Err(RecvTimeoutError::Timeout) => return Err("the bot didn't answer in time".to_owned()),
+                Err(RecvTimeoutError::Disconnected) => return Err("the bot exited".to_owned()),
+            };
+            match response {
+                Response::Unknown => continue,
+                Response::Error { reason } => return Err(format!("the bot failed: {}", reason)),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    /// The placement suggested by the bot for the current piece, once it answered. It's asked on
+    /// the first call for every piece.
+    pub fn suggestion(&mut self, tetris: &Tetris) -> Result<Option<Tetrimino>, String> {
+        if tetris.current_piece.is_none() {
+            return Ok(None);
+        }
+        if let Some(ref suggestion) = self.suggestion {
+            if suggestion.nb_pieces == tetris.nb_pieces {
+                return Ok(Some(suggestion.target.clone()));
+            }
+        }
+
+        // only one suggestion is asked at a time, an answer coming after its piece was placed is dropped
+        let (nb_pieces, asked) = match self.asked {
+            Some(asked) => asked,
+            None => {
+                self.start(tetris)?;
+                self.send(&Request::Suggest)?;
+                self.asked = Some((tetris.nb_pieces, Instant::now()));
+                return Ok(None);
+            }
+        };
+        let moves = loop {
+            match self.responses.try_recv() {
+                Ok(Ok(Response::Suggestion { moves })) => break moves,
+                Ok(Ok(Response::Error { reason })) => return Err(format!("the bot failed: {}", reason)),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(e),
+                Err(TryRecvError::Empty) if asked.elapsed() > self.timeout =>
+                    return Err("the bot didn't answer in time".to_owned()),
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err("the bot exited".to_owned()),
+            }
+        };
+
+        self.asked = None;
+        if nb_pieces != tetris.nb_pieces {
+            return Ok(None);
+        }
+        let candidates = candidates(tetris);
+        let suggestion = moves.iter().find_map(|suggested| resolve(tetris, &candidates, suggested))
+            .ok_or("none of the moves suggested by the bot can be played")?;
+        let target = suggestion.target.clone();
+        self.suggestion = Some(suggestion);
+        Ok(Some(target))
+    }
+
+    /// Plays the placement suggested by the bot as soon as it answered. Returns true if the current
+    /// piece was made permanent.
+    pub fn play(&mut self, tetris: &mut Tetris) -> Result<bool, String> {
+        let target = match self.suggestion(tetris)? {
+            Some(target) => target,
+            None => return Ok(false),
+        };
+        let hold = self.suggestion.as_ref().is_some_and(|suggestion| suggestion.hold);
+        self.suggestion = None;
+        if hold {
+            tetris.apply(Input::Hold, false);
+        }
+
+        let cells = target.cells();
+        let placement = match tetris.current_piece {
+            Some(ref piece) => movegen::placements(&tetris.game_map, piece).into_iter()
+                .filter(|placement| placement.cells == cells)
+                .min_by_key(|placement| placement.t_spin != target.rotated)
+                .map(|placement| (placement, piece.clone())),
+            None => None,
+        };
+        let (placement, mut piece) = match placement {
+            Some(placement) => placement,
+            // the piece fell past the way to the placement, the bot is asked again on the next call
+            None => return Ok(false),
+        };
+
+        piece.x = placement.x;
+        piece.y = placement.y;
+        piece.current_state = placement.state;
+        let played = Move {
+            location: location(&piece, tetris)?,
+            spin: if placement.t_spin { Spin::Full } else { Spin::None },
+        };
+        if let Some(ref mut model) = self.model {
+            model.play(&cells, hold);
+        }
+        self.send(&Request::Play { played })?;
+        Ok(placement.inputs.iter().any(|input| tetris.apply(*input, false)))
+    }
+
+    /// Tells the bot about the pieces which came in the preview since it was started, or starts it
+    /// again if the game went another way than it knows, after garbage for example.
+    fn start(&mut self, tetris: &Tetris) -> Result<(), String> {
+        let model = Model::of(tetris)?;
+
+        match self.model {
+            Some(ref known) if known.board == model.board && known.hold == model.hold &&
+                model.queue.starts_with(&known.queue) => {
+                for &piece in model.queue[known.queue.len()..].iter() {
+                    self.send(&Request::NewPiece { piece })?;
+                }
+            }
+            ref known => {
+                if known.is_some() {
+                    self.send(&Request::Stop)?;
+                }
+                let height = tetris.game_map.len();
+                let board = (0..BOARD_HEIGHT)
+                    .map(|y| (0..tetris.game_map[0].len())
+                        .map(|x| match height.checked_sub(y + 1) {
+                            Some(line) if tetris.game_map[line][x] != 0 => Some(letter(tetris, line, x)),
+                            _ => None,
+                        })
+                        .collect())
+                    .collect();
+                self.send(&Request::Start {
+                    hold: model.hold,
+                    queue: model.queue.clone(),
+                    combo: 0,
+                    back_to_back: false,
+                    board,
+                })?;
+            }
+        }
+        self.model = Some(model);
+        Ok(())
+    }
+}
+
+impl Drop for Bot {
+    fn drop(&mut self) {
+        let _ = self.send(&Request::Quit);
+
+        if let Some(ref mut child) = self.child {
+            let quit = Instant::now();
+            while quit.elapsed() < QUIT_DELAY {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Model {
+    fn of(tetris: &Tetris) -> Result<Model, String> {
+        if tetris.rules.scale() != 1 || tetris.game_map[0].len() != 10 {
+            return Err("the bots can't play on a board of this size".to_owned());
+        }
+        let letters = |names: Vec<char>| names.into_iter()
+            .map(|name| letter_of(tetris, name).ok_or(format!("the bots can't play with the piece {}", name)))
+            .collect::<Result<Vec<_>, _>>();
+
+        let mut queue = tetris.current_piece.iter().map(|piece| piece.name).collect::<Vec<_>>();
+        queue.extend(tetris.preview(PREVIEW_PIECES));
+        Ok(Model {
+            board: tetris.game_map.iter().map(|line| line.iter().map(|case| *case != 0).collect()).collect(),
+            hold: letters(tetris.held_piece().into_iter().collect())?.first().cloned(),
+            queue: letters(queue)?,
+        })
+    }
+
+    /// Places a piece as the game will, the held one when `hold` is true.
+    fn play(&mut self, cells: &[(usize, usize)], hold: bool) {
+        for &(x, y) in cells.iter() {
+            self.board[y][x] = true;
+        }
+        let height = self.board.len();
+        let width = self.board[0].len();
+        self.board.retain(|line| line.contains(&false));
+        while self.board.len() < height {
+            self.board.insert(0, vec![false; width]);
+        }
+
+        if hold {
+            if self.hold.is_none() && !self.queue.is_empty() {
+                self.hold = Some(self.queue.remove(0));
+            } else if let Some(ref mut held) = self.hold {
+                if let Some(first) = self.queue.first_mut() {
+                    std::mem::swap(held, first);
+                }
+            }
+        }
+        if !self.queue.is_empty() {
+            self.queue.remove(0);
+        }
+    }
+}
+
+/// The pieces which can be placed, the current one and the one it's swapped with if it can be held.
+fn candidates(tetris: &Tetris) -> Vec<Candidate> {
+    let piece_set = &tetris.rules.piece_set;
+    let mut pieces = tetris.current_piece.iter().map(|piece| (false, piece.clone())).collect::<Vec<_>>();
+    if tetris.can_hold() {
+        let name = tetris.held_piece().or_else(|| tetris.preview(1).first().cloned());
+        pieces.extend(name.and_then(|name| piece_set.position(name)).map(|index| (true, piece_set.create(index))));
+    }
+
+    pieces.into_iter()
+        .filter_map(|(hold, piece)| {
+            let reachable = movegen::placements(&tetris.game_map, &piece).into_iter()
+                .map(|placement| placement.cells)
+                .collect();
+            Some(Candidate { hold, letter: letter_of(tetris, piece.name)?, reachable })
+        })
+        .collect()
+}
+
+/// The placement of a move of the bot, if one of the pieces can be placed there.
+fn resolve(tetris: &Tetris, candidates: &[Candidate], suggested: &Move) -> Option<Suggestion> {
+    let location = &suggested.location;
+    let candidate = candidates.iter().find(|candidate| candidate.letter == location.piece)?;
+    let piece_type = LETTERS.iter().position(|letter| *letter == location.piece)? as u8 + 1;
+
+    let piece_set = &tetris.rules.piece_set;
+    let mut target = fumen::decode_piece(piece_type, location.orientation.rotation(), location.x, location.y,
+                                         piece_set, &fumen::piece_types(piece_set)).ok()?;
+    if !candidate.reachable.contains(&target.cells()) {
+        return None;
+    }
+    // the spin is kept along with the target, to play it with a spin when it can be
+    target.rotated = suggested.spin != Spin::None;
+    Some(Suggestion { nb_pieces: tetris.nb_pieces, hold: candidate.hold, target })
+}
+
+/// The letter of the piece of the set with that name, None if it isn't a tetrimino.
+fn letter_of(tetris: &Tetris, name: char) -> Option<char> {
+    let types = fumen::piece_types(&tetris.rules.piece_set);
+    LETTERS.get((*types.get(tetris.rules.piece_set.position(name)?)? as usize).wrapping_sub(1)).cloned()
+}
+
+/// The letter of a case taken of the game map, garbage for the minos of pieces which aren't
+/// tetriminos.
+fn letter(tetris: &Tetris, y: usize, x: usize) -> char {
+    let types = fumen::piece_types(&tetris.rules.piece_set);
+    types.get((tetris.game_map[y][x] as usize).wrapping_sub(1))
+        .and_then(|piece_type| LETTERS.get((*piece_type as usize).wrapping_sub(1)))
+        .cloned()
+        .unwrap_or(GARBAGE_LETTER)
+}
+
+fn location(piece: &Tetrimino, tetris: &Tetris) -> Result<Location, String> {
+    let (piece_type, rotation, x, y) = fumen::encode_piece(piece, &fumen::piece_types(&tetris.rules.piece_set))?;
+    Ok(Location {
+        piece: LETTERS[piece_type as usize - 1],
+        orientation: Orientation::of(rotation),
+        x,
+        y,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{pipe, PipeWriter};
+    use std::thread::JoinHandle;
+
+    use serde_json::{json, Value};
+
+    use crate::game_mode::GameMode;
+    use crate::rules::Rules;
+    use crate::tetris_struct::GARBAGE;
+
+    /// What the mock bot does when it's asked for a suggestion.
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        // suggests every position of the current piece, from the bottom of the map
+        Plays,
+        // the same with the piece it's swapped with
+        Holds,
+        Silent,
+        Garbage,
+        Exits,
+        RefusesRules,
+        Unplayable,
+    }
+
+    /// Connects to a mock bot answering in a thread, which returns the messages it received once it quits.
+    fn mock(behaviour: Behaviour) -> (Result<Bot, String>, JoinHandle<Vec<Value>>) {
+        let (requests, to_bot) = pipe().unwrap();
+        let (from_bot, mut responses) = pipe().unwrap();
+        let mock = thread::spawn(move || {
+            let send = |responses: &mut PipeWriter, response: Value| writeln!(responses, "{}", response).is_ok();
+            send(&mut responses, json!({"type": "info", "name": "Mock", "version": "1.0", "author": "tests",
+                                        "features": []}));
+            let mut queue: Vec<char> = Vec::new();
+            let mut hold = None;
+            let mut received = Vec::new();
+
+            for line in BufReader::new(requests).lines() {
+                let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                received.push(request.clone());
+                let response = match (request["type"].as_str().unwrap(), behaviour) {
+                    ("rules", Behaviour::RefusesRules) => json!({"type": "error", "reason": "unsupported rules"}),
+                    ("rules", _) => json!({"type": "ready"}),
+                    ("start", _) => {
+                        queue = serde_json::from_value(request["queue"].clone()).unwrap();
+                        hold = serde_json::from_value(request["hold"].clone()).unwrap();
+                        continue;
+                    }
+                    ("new_piece", _) => {
+                        queue.push(serde_json::from_value(request["piece"].clone()).unwrap());
+                        continue;
+                    }
+                    ("play", _) => {
+                        if request["move"]["location"]["type"] != queue[0].to_string() {
+                            match hold {
+                                Some(ref mut held) => std::mem::swap(held, &mut queue[0]),
+                                None => hold = Some(queue.remove(0)),
+                            }
+                        }
+                        queue.remove(0);
+                        continue;
+                    }
+                    ("suggest", Behaviour::Plays | Behaviour::Holds) => {
+                        let piece = match behaviour {
+                            Behaviour::Holds => hold.unwrap_or(queue[1]),
+                            _ => queue[0],
+                        };
+                        let moves = (0..16).flat_map(|y| (0..10).flat_map(move |x| {
+                            ["north", "east", "south", "west"].map(|orientation| json!({
+                                "location": {"type": piece, "orientation": orientation, "x": x, "y": y},
+                                "spin": "none",
+                            }))
+                        })).collect::<Vec<_>>();
+                        json!({"type": "suggestion", "moves": moves})
+                    }
+                    ("suggest", Behaviour::Unplayable) => json!({"type": "suggestion", "moves": [{
+                        "location": {"type": queue[0], "orientation": "north", "x": -5, "y": 0},
+                        "spin": "none",
+                    }]}),
+                    ("suggest", Behaviour::Garbage) => {
+                        if writeln!(responses, "not a message").is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    ("suggest", Behaviour::Exits) | ("quit", _) => break,
+                    _ => continue,
+                };
+                if !send(&mut responses, response) {
+                    break;
+                }
+            }
+            received
+        });
+        (Bot::connect(None, to_bot, from_bot, Duration::from_millis(500)), mock)
+    }
+
+    /// The error the bot stops with when it plays the game.
+    fn failure(behaviour: Behaviour) -> String {
+        let mut bot = mock(behaviour).0.unwrap();
+        let mut tetris = Tetris::with_seed(GameMode::Zen, Rules::default(), 1);
+        let start = Instant::now();
+
+        while start.elapsed() < Duration::from_secs(10) {
+            if let Err(e) = bot.play(&mut tetris) {
+                return e;
+            }
+            tetris.tick();
+        }
+        panic!("the bot didn't fail");
+    }
+
+    /// Lets the bot place 20 pieces, and returns the messages it received.
+    fn play(behaviour: Behaviour, rules: Rules) -> Vec<Value> {
+        let (bot, mock) = mock(behaviour);
+        let mut bot = bot.unwrap();
+        assert_eq!(bot.name, "Mock");
+        let mut tetris = Tetris::with_seed(GameMode::Zen, rules, 1);
+        tetris.game_map[15][0] = GARBAGE;
+        let start = Instant::now();
+
+        // the game waits for the bot, the pieces would fall before it answers otherwise
+        while tetris.nb_pieces < 20 {
+            if tetris.current_piece.is_none() || bot.play(&mut tetris).unwrap() {
+                tetris.tick();
+            }
+            assert!(!tetris.is_game_over());
+            assert!(start.elapsed() < Duration::from_secs(60));
+        }
+        drop(bot);
+        mock.join().unwrap()
+    }
+
+    #[test]
+    fn mock_bot_plays_the_game() {
+        let received = play(Behaviour::Plays, Rules::default());
+        let count = |kind: &str| received.iter().filter(|request| request["type"] == kind).count();
+        // the bot keeps up with the game from the pieces it plays and the ones added to the preview
+        assert_eq!(count("start"), 1);
+        assert_eq!(count("play"), 20);
+        assert_eq!(count("quit"), 1);
+        let board = received.iter().find(|request| request["type"] == "start").unwrap()["board"].as_array().unwrap();
+        assert_eq!(board.len(), BOARD_HEIGHT);
+        assert_eq!(board[0][0], "G");
+        assert_eq!(board[0][1], Value::Null);
+    }
+
+    #[test]
+    fn mock_bot_holds_pieces() {
+        let received = play(Behaviour::Holds, Rules { hold: true, ..Rules::default() });
+
+        assert_eq!(received.iter().filter(|request| request["type"] == "start").count(), 1);
+        assert_eq!(received.iter().filter(|request| request["type"] == "play").count(), 20);
+    }
+
+    #[test]
+    fn misbehaving_bots_are_stopped() {
+        assert_eq!(failure(Behaviour::Silent), "the bot didn't answer in time");
+        assert!(failure(Behaviour::Garbage).starts_with("invalid message from the bot"));
+        assert_eq!(failure(Behaviour::Exits), "the bot exited");
+        assert_eq!(failure(Behaviour::Unplayable), "none of the moves suggested by the bot can be played");
+        assert_eq!(mock(Behaviour::RefusesRules).0.err().unwrap(), "the bot failed: unsupported rules");
+    }
+}
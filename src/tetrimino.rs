@@ -1,43 +1,113 @@
 extern crate rand;
 
+use serde::{Serialize, Deserialize};
+
 
 type Piece = Vec<Vec<u8>>;
 type States = Vec<Piece>;
 
+// (x, y) offsets tried in order when rotating a piece, a negative y moves the piece up
+pub const DEFAULT_KICKS: [(isize, isize); 6] = [(0, 0), (-1, 0), (1, 0), (-2, 0), (2, 0), (-3, 0)];
+
 
 pub trait TetriminoGenerator {
     fn new() -> Tetrimino;
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Tetrimino {
+    pub name: char,
     pub states: States,
     pub x: isize,
     pub y: usize,
     pub current_state: u8,
+    // true if the last successful move of the piece was a rotation
+    pub rotated: bool,
+    // number of cases the piece moves by, each mino is step x step cases big
+    pub step: usize,
+    pub kicks: Vec<(isize, isize)>,
 }
 
 impl Tetrimino {
+    /// Makes every mino of the piece `scale` x `scale` cases big, the piece then moves by
+    /// `scale` cases.
+    pub fn scaled(mut self, scale: usize) -> Tetrimino {
+        self.states = self.states.iter()
+            .map(|state| state.iter()
+                .flat_map(|line| {
+                    let line = line.iter()
+                        .flat_map(|case| std::iter::repeat_n(*case, scale))
+                        .collect::<Vec<_>>();
+                    std::iter::repeat_n(line, scale)
+                })
+                .collect())
+            .collect();
+        self.x *= scale as isize;
+        self.step = scale;
+        self
+    }
+
     pub fn rotate(&mut self, game_map: &[Vec<u8>]) {
-        let mut tmp_state = self.current_state + 1;
-        if self.current_state as usize >= self.states.len() {
-            self.current_state = 0;
-        }
-        let x_pos = [0, -1, 1, -2, 2, -3];
-        for x in x_pos.iter() {
-            if self.test_position(game_map, tmp_state as usize, self.x + x, self.y) == true {
+        let tmp_state = (self.current_state + 1) % self.states.len() as u8;
+        for &(x, y) in self.kicks.iter() {
+            let (x, y) = (x * self.step as isize, y * self.step as isize);
+            if self.y as isize + y < 0 {
+                continue;
+            }
+            let y = (self.y as isize + y) as usize;
+            if self.test_position(game_map, tmp_state as usize, self.x + x, y) {
                 self.current_state = tmp_state;
-                self.x += *x;
+                self.x += x;
+                self.y = y;
+                self.rotated = true;
                 break;
             }
         }
     }
 
+    /// Checks the three corners rule: a T piece which was rotated into a position where at
+    /// least three of the four cells diagonal to its center are occupied.
+    pub fn is_t_spin(&self, game_map: &[Vec<u8>]) -> bool {
+        if self.name != 'T' || !self.rotated {
+            return false;
+        }
+        let state = &self.states[self.current_state as usize];
+        let step = self.step as isize;
+        // coordinates are in minos, not in cases
+        let filled = |x: isize, y: isize| {
+            let (x, y) = (x * step, y * step);
+            x >= 0 && y >= 0 && (y as usize) < state.len() && (x as usize) < state[y as usize].len() &&
+                state[y as usize][x as usize] != 0
+        };
+
+        // the center of the T is its only mino with three neighbours
+        let center = (0..state.len() as isize / step)
+            .flat_map(|y| (0..state[0].len() as isize / step).map(move |x| (x, y)))
+            .find(|&(x, y)| filled(x, y) &&
+                [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+                    .filter(|&&(dx, dy)| filled(x + dx, y + dy)).count() == 3);
+        let (center_x, center_y) = match center {
+            Some(center) => center,
+            None => return false,
+        };
+
+        let occupied_corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter()
+            .filter(|&&(dx, dy)| {
+                let x = self.x + (center_x + dx) * step;
+                let y = self.y as isize + (center_y + dy) * step;
+                // walls and floor count as occupied, the space above the map doesn't
+                x < 0 || y >= game_map.len() as isize || x >= game_map[0].len() as isize ||
+                    (y >= 0 && game_map[y as usize][x as usize] != 0)
+            }).count();
+        occupied_corners >= 3
+    }
+
     pub fn test_position(&self, game_map: &[Vec<u8>], tmp_state: usize,
                          x: isize, y: usize) -> bool {
-        for decal_y in 0..4 {
-            for decal_x in 0..4 {
-                let x = x + decal_x;
-                if self.states[tmp_state][decal_y][decal_x as usize] != 0 &&
+        for (decal_y, line) in self.states[tmp_state].iter().enumerate() {
+            for (decal_x, case) in line.iter().enumerate() {
+                let x = x + decal_x as isize;
+                if *case != 0 &&
                     (y + decal_y >= game_map.len() || x < 0 ||
                         x as usize >= game_map[y + decal_y].len() ||
                         game_map[y + decal_y][x as usize] != 0) {
@@ -49,7 +119,11 @@ impl Tetrimino {
     }
 
     pub fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
-        if self.test_position(game_map, self.current_state as usize, new_x, new_y) == true {
+        if self.test_position(game_map, self.current_state as usize, new_x, new_y) {
+            // a T-spin needs the rotation to be the last move, falling included
+            if new_x != self.x || new_y != self.y {
+                self.rotated = false;
+            }
             self.x = new_x;
             self.y = new_y;
             true
@@ -61,6 +135,21 @@ impl Tetrimino {
     pub fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
         self.test_position(game_map, self.current_state as usize, self.x, self.y)
     }
+
+    /// The cases taken by the piece, sorted.
+    pub fn cells(&self) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+
+        for (shift_y, line) in self.states[self.current_state as usize].iter().enumerate() {
+            for (shift_x, case) in line.iter().enumerate() {
+                if *case != 0 {
+                    cells.push(((self.x + shift_x as isize) as usize, self.y + shift_y));
+                }
+            }
+        }
+        cells.sort();
+        cells
+    }
 }
 
 pub struct TetriminoI;
@@ -68,6 +157,7 @@ pub struct TetriminoI;
 impl TetriminoGenerator for TetriminoI {
     fn new() -> Tetrimino {
         Tetrimino {
+            name: 'I',
             states: vec![vec![vec![1, 1, 1, 1],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0],
@@ -79,6 +169,9 @@ impl TetriminoGenerator for TetriminoI {
             x: 4,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
@@ -88,25 +181,29 @@ pub struct TetriminoJ;
 impl TetriminoGenerator for TetriminoJ {
     fn new() -> Tetrimino {
         Tetrimino {
-            states: vec![vec![vec![2, 2, 2, 2],
-                              vec![2, 0, 0, 0],
+            name: 'J',
+            states: vec![vec![vec![2, 2, 2, 0],
+                              vec![0, 0, 2, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0], ],
-                         vec![vec![2, 2, 0, 0],
-                              vec![0, 2, 0, 0],
+                         vec![vec![0, 2, 0, 0],
                               vec![0, 2, 0, 0],
+                              vec![2, 2, 0, 0],
                               vec![0, 0, 0, 0], ],
-                         vec![vec![0, 0, 2, 0],
+                         vec![vec![2, 0, 0, 0],
                               vec![2, 2, 2, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0], ],
-                         vec![vec![2, 0, 0, 0],
+                         vec![vec![2, 2, 0, 0],
+                              vec![2, 0, 0, 0],
                               vec![2, 0, 0, 0],
-                              vec![2, 2, 0, 0],
                               vec![0, 0, 0, 0], ]],
             x: 4,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
@@ -116,25 +213,29 @@ pub struct TetriminoL;
 impl TetriminoGenerator for TetriminoL {
     fn new() -> Tetrimino {
         Tetrimino {
+            name: 'L',
             states: vec![vec![vec![3, 3, 3, 0],
-                              vec![0, 0, 3, 0],
+                              vec![3, 0, 0, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0], ],
-                         vec![vec![0, 3, 0, 0],
+                         vec![vec![3, 3, 0, 0],
+                              vec![0, 3, 0, 0],
                               vec![0, 3, 0, 0],
-                              vec![3, 3, 0, 0],
                               vec![0, 0, 0, 0], ],
-                         vec![vec![3, 0, 0, 0],
+                         vec![vec![0, 0, 3, 0],
                               vec![3, 3, 3, 0],
                               vec![0, 0, 0, 0],
                               vec![0, 0, 0, 0], ],
-                         vec![vec![3, 3, 0, 0],
-                              vec![3, 0, 0, 0],
+                         vec![vec![3, 0, 0, 0],
                               vec![3, 0, 0, 0],
+                              vec![3, 3, 0, 0],
                               vec![0, 0, 0, 0], ]],
             x: 4,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
@@ -144,6 +245,7 @@ pub struct TetriminoO;
 impl TetriminoGenerator for TetriminoO {
     fn new() -> Tetrimino {
         Tetrimino {
+            name: 'O',
             states: vec![vec![vec![4, 4, 0, 0],
                               vec![4, 4, 0, 0],
                               vec![0, 0, 0, 0],
@@ -152,6 +254,9 @@ impl TetriminoGenerator for TetriminoO {
             x: 5,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
@@ -161,6 +266,7 @@ pub struct TetriminoS;
 impl TetriminoGenerator for TetriminoS {
     fn new() -> Tetrimino {
         Tetrimino {
+            name: 'S',
             states: vec![vec![vec![0, 5, 5, 0],
                               vec![5, 5, 0, 0],
                               vec![0, 0, 0, 0],
@@ -173,6 +279,9 @@ impl TetriminoGenerator for TetriminoS {
             x: 4,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
@@ -183,6 +292,7 @@ pub struct TetriminoZ;
 impl TetriminoGenerator for TetriminoZ {
     fn new() -> Tetrimino {
         Tetrimino {
+            name: 'Z',
             states: vec![vec![vec![6, 6, 0, 0],
                               vec![0, 6, 6, 0],
                               vec![0, 0, 0, 0],
@@ -195,6 +305,9 @@ impl TetriminoGenerator for TetriminoZ {
             x: 4,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
@@ -204,6 +317,7 @@ pub struct TetriminoT;
 impl TetriminoGenerator for TetriminoT {
     fn new() -> Tetrimino {
         Tetrimino {
+            name: 'T',
             states: vec![vec![vec![7, 7, 7, 0],
                               vec![0, 7, 0, 0],
                               vec![0, 0, 0, 0],
@@ -223,6 +337,9 @@ impl TetriminoGenerator for TetriminoT {
             x: 4,
             y: 0,
             current_state: 0,
+            rotated: false,
+            step: 1,
+            kicks: DEFAULT_KICKS.to_vec(),
         }
     }
 }
\ No newline at end of file
@@ -1,5 +1,14 @@
-use std::time::SystemTime;
-use crate::tetrimino::{Tetrimino, TetriminoJ, TetriminoI, TetriminoT, TetriminoZ, TetriminoS, TetriminoO, TetriminoL, TetriminoGenerator};
+use std::cmp::Reverse;
+
+use serde::{Serialize, Deserialize};
+
+use crate::finesse;
+use crate::game_mode::{GameMode, frames_to_millis};
+use crate::puzzle::EMPTY;
+use crate::random::Random;
+use crate::replay::{Input, Replay};
+use crate::rules::{Rules, StackVisibility};
+use crate::tetrimino::Tetrimino;
 
 
 const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250,
@@ -8,57 +17,642 @@ const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250,
 const LEVEL_LINES: [u32; 10] = [20, 40, 60, 80, 100, 120, 140, 160,
     180, 200];
 
+pub const MAP_WIDTH: usize = 10;
+pub const MAP_HEIGHT: usize = 16;
+pub const TICKS_PER_SECOND: u64 = 60;
+// gravity is expressed in 1/GRAVITY_UNIT lines per tick
+pub const GRAVITY_UNIT: u32 = 65536;
+pub const GARBAGE: u8 = 255;
+// number of lines cleared from the top of the map when topping out in Zen mode
+const ZEN_CLEARED_LINES: usize = 8;
+// duration of the fade out of a locked mino with a fading stack
+const FADE_TICKS: u64 = 30;
+// duration of the outline flash of the stack after a line clear with an invisible stack
+const OUTLINE_FLASH_TICKS: u64 = 20;
+// number of placements which can be taken back
+const UNDO_LIMIT: usize = 1000;
+// percentage of chance for the hole of the garbage received in Versus mode to change column
+const VERSUS_MESSINESS: u32 = 30;
+// mixed into the seed of the game to draw the garbage apart from the pieces
+const GARBAGE_SEED: u64 = 0x6761_7262_6167_6521;
+
 type GameMap = Vec<Vec<u8>>;
+// coordinates of the cases of a group of connected minos
+type Group = Vec<(usize, usize)>;
+
+pub enum CellVisibility {
+    Visible,
+    // opacity between 0 and 255
+    Faded(u8),
+    Outline,
+    Hidden,
+}
+
+/// Speed of the game at a given time, all durations are in ticks.
+pub struct Speed {
+    pub gravity: u32,
+    // delay before a new piece appears
+    pub are: u32,
+    // delay before a piece which touches the ground is made permanent
+    pub lock_delay: u32,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ClearStats {
+    pub singles: u32,
+    pub doubles: u32,
+    pub triples: u32,
+    pub tetrises: u32,
+    pub t_spins: u32,
+    pub all_clears: u32,
+    // line clears caused by a cascade, and the longest chain of clears
+    pub chains: u32,
+    pub best_chain: u32,
+    // lines sent to an opponent
+    #[serde(default)]
+    pub attack: u32,
+}
+
+impl ClearStats {
+    fn add_clear(&mut self, nb_lines: u32, t_spin: bool, all_clear: bool) {
+        match nb_lines {
+            1 => self.singles += 1,
+            2 => self.doubles += 1,
+            3 => self.triples += 1,
+            4 => self.tetrises += 1,
+            _ => {}
+        }
+        if t_spin {
+            self.t_spins += 1;
+        }
+        if all_clear {
+            self.all_clears += 1;
+        }
+        // as in the guideline, a T-spin sends twice the lines it clears
+        self.attack += match (t_spin, nb_lines) {
+            (true, _) => nb_lines * 2,
+            (false, 2) => 1,
+            (false, 3) => 2,
+            (false, 4) => 4,
+            _ => 0,
+        } + if all_clear { 10 } else { 0 };
+    }
+
+    fn add_chain(&mut self, chain: u32) {
+        self.chains += 1;
+        self.best_chain = self.best_chain.max(chain);
+    }
+}
 
+/// State of the game when a piece spawns, to go back to it.
+#[derive(Clone, Serialize, Deserialize)]
+struct Snapshot {
+    game_map: GameMap,
+    current_level: u32,
+    score: u32,
+    nb_lines: u32,
+    nb_pieces: u32,
+    clear_stats: ClearStats,
+    current_piece: Option<Tetrimino>,
+    mode: GameMode,
+    random: Random,
+    previous_piece: u32,
+    garbage_random: Random,
+    garbage_lines: Vec<bool>,
+    garbage_hole: Option<usize>,
+    locked_at: Vec<Vec<u64>>,
+    held_piece: Option<char>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Tetris {
     pub game_map: GameMap,
     pub current_level: u32,
     pub score: u32,
     pub nb_lines: u32,
+    // number of pieces made permanent
+    pub nb_pieces: u32,
+    pub clear_stats: ClearStats,
+    // keys pressed, the repetitions of a held key left aside, and the keys pressed beyond the
+    // fewest needed for the placements
+    pub nb_keys: u32,
+    pub finesse_faults: u32,
     pub current_piece: Option<Tetrimino>,
+    pub mode: GameMode,
+    pub rules: Rules,
+    pub frame: u64,
+    gravity: u32,
+    lock_frames: u32,
+    are_frames: u32,
+    soft_drop_rows: u32,
+    game_over: bool,
+    random: Random,
+    previous_piece: u32,
+    // the holes of the garbage have their own generator, so the garbage received doesn't change
+    // the pieces to come
+    garbage_random: Random,
+    // for each line of game_map, true if it is a garbage line which hasn't been cleared yet
+    garbage_lines: Vec<bool>,
+    garbage_hole: Option<usize>,
+    // for each case of game_map, the tick at which it was made permanent
+    locked_at: Vec<Vec<u64>>,
+    last_clear: Option<u64>,
+    revealed_lines: usize,
+    held_piece: Option<char>,
+    // a piece can only be held once until it's made permanent
+    hold_used: bool,
+    // the state at the spawn of the current piece, and at the spawn of the pieces made permanent
+    // or taken back, only kept in the modes where they can be taken back
+    spawn_state: Option<Snapshot>,
+    undo_stack: Vec<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+    replay: Replay,
+    // the current piece as it spawned, and the keys pressed to move it since then
+    finesse_start: Option<Tetrimino>,
+    piece_keys: u32,
 }
 
 impl Tetris {
-    pub(crate) fn new() -> Tetris {
+    pub(crate) fn new(mode: GameMode, rules: Rules) -> Tetris {
+        Tetris::with_seed(mode, rules, rand::random())
+    }
+
+    /// Creates a game whose pieces and garbage only depend on `seed`, so it can be replayed.
+    pub(crate) fn with_seed(mode: GameMode, rules: Rules, seed: u64) -> Tetris {
         let mut game_map = Vec::new();
+        let (width, height) = (MAP_WIDTH * rules.scale(), MAP_HEIGHT * rules.scale());
 
-        for _ in 0..16 {
-            game_map.push(vec![0; 10]);
+        for _ in 0..height {
+            game_map.push(vec![0; width]);
         }
-        Tetris {
+        let replay = Replay::new(seed, rules.clone(), mode.clone());
+        let mut tetris = Tetris {
             game_map,
             current_level: 1,
             score: 0,
             nb_lines: 0,
+            nb_pieces: 0,
+            clear_stats: ClearStats::default(),
+            nb_keys: 0,
+            finesse_faults: 0,
             current_piece: None,
+            mode,
+            rules,
+            frame: 0,
+            gravity: 0,
+            lock_frames: 0,
+            are_frames: 0,
+            soft_drop_rows: 0,
+            game_over: false,
+            random: Random::new(seed),
+            previous_piece: u32::MAX,
+            garbage_random: Random::new(seed ^ GARBAGE_SEED),
+            garbage_lines: vec![false; height],
+            garbage_hole: None,
+            locked_at: vec![vec![0; width]; height],
+            last_clear: None,
+            revealed_lines: 0,
+            held_piece: None,
+            hold_used: false,
+            spawn_state: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            replay,
+            finesse_start: None,
+            piece_keys: 0,
+        };
+
+        if let GameMode::Dig { garbage_lines, messiness } = tetris.mode {
+            tetris.add_garbage(garbage_lines, messiness);
+        }
+        if let GameMode::Puzzle(ref puzzle) = tetris.mode {
+            let map = puzzle.map.clone();
+            tetris.load_map(&map);
+        }
+        tetris
+    }
+
+    pub fn speed(&self) -> Speed {
+        let level_time = match self.mode {
+            GameMode::Master(ref master) => return master.speed(),
+            // gravity doesn't increase with the level in Zen mode
+            GameMode::Zen => LEVEL_TIMES[0],
+            _ => LEVEL_TIMES[(self.current_level as usize - 1).min(LEVEL_TIMES.len() - 1)],
+        };
+        let ticks_per_line = level_time * TICKS_PER_SECOND as u32 / 1000;
+
+        // the piece is made permanent when gravity can't move it down anymore
+        Speed {
+            gravity: GRAVITY_UNIT / ticks_per_line,
+            are: 0,
+            lock_delay: ticks_per_line,
+        }
+    }
+
+    /// Advances the game by one engine tick: spawns a new piece once the ARE is over, applies
+    /// gravity to the current piece and makes it permanent when its lock delay is over.
+    pub fn tick(&mut self) {
+        if self.game_over {
+            return;
+        }
+        self.frame += 1;
+        let speed = self.speed();
+
+        if self.current_piece.is_none() {
+            if self.are_frames < speed.are {
+                self.are_frames += 1;
+                return;
+            }
+            if !self.spawn_piece() {
+                self.game_over = true;
+                return;
+            }
+            if self.mode.can_undo() {
+                self.spawn_state = Some(self.snapshot());
+            }
+        }
+
+        // with 20G (or more) the piece falls instantly, even on the tick it spawns
+        self.gravity += speed.gravity;
+        while self.gravity >= GRAVITY_UNIT {
+            self.gravity -= GRAVITY_UNIT;
+            if !self.move_down() {
+                self.gravity = 0;
+            }
+        }
+
+        if self.is_on_ground() {
+            self.lock_frames += 1;
+            if self.lock_frames >= speed.lock_delay {
+                self.make_permanent();
+            }
+        }
+    }
+
+    fn move_down(&mut self) -> bool {
+        if let Some(ref mut piece) = self.current_piece {
+            let x = piece.x;
+            let y = piece.y + piece.step;
+            if piece.change_position(&self.game_map, x, y) {
+                // the lock delay is reset every time the piece goes down
+                self.lock_frames = 0;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn is_on_ground(&self) -> bool {
+        match self.current_piece {
+            Some(ref piece) => !piece.test_position(&self.game_map, piece.current_state as usize,
+                                                    piece.x, piece.y + piece.step),
+            None => false,
+        }
+    }
+
+    /// Drops the current piece as low as it goes and makes it permanent. Returns true if there was
+    /// a piece to drop.
+    pub fn hard_drop(&mut self) -> bool {
+        match self.current_piece {
+            Some(ref mut piece) => {
+                let x = piece.x;
+                let mut y = piece.y;
+
+                while piece.change_position(&self.game_map, x, y + piece.step) {
+                    y += piece.step;
+                }
+            }
+            None => return false,
+        }
+        self.make_permanent();
+        true
+    }
+
+    /// Applies an input of the player and records it in the replay of the game, `repeat` being
+    /// true when it comes from a key held down. Returns true if the current piece was made
+    /// permanent.
+    pub fn apply(&mut self, input: Input, repeat: bool) -> bool {
+        self.replay.record(self.frame, input, repeat);
+        self.play(input, repeat)
+    }
+
+    /// Keys pressed per piece made permanent.
+    pub fn kpp(&self) -> f32 {
+        if self.nb_pieces > 0 { self.nb_keys as f32 / self.nb_pieces as f32 } else { 0.0 }
+    }
+
+    /// Pieces made permanent per second.
+    pub fn pps(&self) -> f32 {
+        let duration = self.elapsed_millis();
+        if duration > 0 { self.nb_pieces as f32 * 1000.0 / duration as f32 } else { 0.0 }
+    }
+
+    /// Whether the current piece can move sideways in the direction of the input.
+    pub fn can_shift(&self, input: Input) -> bool {
+        match self.current_piece {
+            Some(ref piece) => {
+                let step = piece.step as isize;
+                let x = if input == Input::Left { piece.x - step } else { piece.x + step };
+                piece.test_position(&self.game_map, piece.current_state as usize, x, piece.y)
+            }
+            None => false,
+        }
+    }
+
+    /// Applies an input without recording it, to play a replay back.
+    pub fn play(&mut self, input: Input, repeat: bool) -> bool {
+        if !repeat {
+            match input {
+                Input::Left | Input::Right | Input::Rotate => {
+                    self.nb_keys += 1;
+                    self.piece_keys += 1;
+                }
+                Input::SoftDrop | Input::HardDrop | Input::Hold => self.nb_keys += 1,
+                Input::Undo | Input::Redo => {}
+            }
+        }
+        match input {
+            Input::Left | Input::Right => {
+                if let Some(ref mut piece) = self.current_piece {
+                    let step = piece.step as isize;
+                    let x = if input == Input::Left { piece.x - step } else { piece.x + step };
+                    let y = piece.y;
+                    piece.change_position(&self.game_map, x, y);
+                }
+            }
+            Input::Rotate => {
+                if let Some(ref mut piece) = self.current_piece {
+                    piece.rotate(&self.game_map);
+                }
+            }
+            Input::SoftDrop => return self.soft_drop(),
+            Input::HardDrop => return self.hard_drop(),
+            Input::Hold => self.hold(),
+            Input::Undo => {
+                self.undo();
+            }
+            Input::Redo => {
+                self.redo();
+            }
+        }
+        false
+    }
+
+    /// The replay of the game so far.
+    pub fn replay(&self) -> Replay {
+        let mut replay = self.replay.clone();
+        replay.length = self.frame;
+        replay
+    }
+
+    /// Moves the current piece one line down or makes it permanent if it can't move anymore.
+    /// Returns true if the piece was made permanent.
+    pub fn soft_drop(&mut self) -> bool {
+        if self.current_piece.is_none() {
+            return false;
+        }
+        self.gravity = 0;
+
+        if self.move_down() {
+            self.soft_drop_rows += 1;
+            false
+        } else {
+            self.make_permanent();
+            true
+        }
+    }
+
+    pub fn elapsed_millis(&self) -> u64 {
+        frames_to_millis(self.frame)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.mode.is_complete(self)
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        self.game_over
+    }
+
+    /// How a case of the game map must be drawn, depending on the stack visibility rule.
+    pub fn cell_visibility(&self, x: usize, y: usize) -> CellVisibility {
+        if self.game_map[y][x] == 0 {
+            return CellVisibility::Hidden;
+        }
+        if y >= self.game_map.len() - self.revealed_lines {
+            return CellVisibility::Visible;
+        }
+
+        match self.rules.stack_visibility {
+            StackVisibility::Visible => CellVisibility::Visible,
+            StackVisibility::Fading { delay } => {
+                let age = self.frame - self.locked_at[y][x];
+                if age < delay as u64 {
+                    CellVisibility::Visible
+                } else if age < delay as u64 + FADE_TICKS {
+                    CellVisibility::Faded((255 * (delay as u64 + FADE_TICKS - age) / FADE_TICKS) as u8)
+                } else {
+                    CellVisibility::Hidden
+                }
+            }
+            StackVisibility::Invisible => match self.last_clear {
+                Some(last_clear) if self.frame - last_clear < OUTLINE_FLASH_TICKS => CellVisibility::Outline,
+                _ => CellVisibility::Hidden,
+            },
+        }
+    }
+
+    /// Reveals one more line of the stack, from the bottom. Returns false once the whole stack is
+    /// revealed.
+    pub fn reveal_line(&mut self) -> bool {
+        if self.revealed_lines < self.game_map.len() {
+            self.revealed_lines += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn garbage_left(&self) -> u32 {
+        let garbage = self.garbage_lines.iter().filter(|garbage| **garbage).count();
+        (garbage / self.rules.scale()) as u32
+    }
+
+    /// Inserts garbage lines beneath the stack, each with a single hole. `messiness` is the
+    /// percentage of chance for the hole to move to another column from one line to the next.
+    pub fn add_garbage(&mut self, nb_lines: u32, messiness: u32) {
+        let scale = self.rules.scale();
+        // the width in minos, a mino being scale x scale cases big
+        let width = self.game_map[0].len() / scale;
+
+        for _ in 0..nb_lines {
+            let hole = match self.garbage_hole {
+                Some(hole) if self.garbage_random.below(100) >= messiness => hole,
+                Some(hole) => (hole + 1 + self.garbage_random.below(width as u32 - 1) as usize) % width,
+                None => self.garbage_random.below(width as u32) as usize,
+            };
+            let mut line = vec![GARBAGE; width * scale];
+            line[hole * scale..(hole + 1) * scale].iter_mut().for_each(|case| *case = 0);
+
+            for _ in 0..scale {
+                // the top line is pushed out of the map
+                self.game_map.remove(0);
+                self.garbage_lines.remove(0);
+                self.locked_at.remove(0);
+                self.game_map.push(line.clone());
+                self.garbage_lines.push(true);
+                self.locked_at.push(vec![self.frame; width * scale]);
+            }
+            self.garbage_hole = Some(hole);
+        }
+
+        if let Some(ref mut piece) = self.current_piece {
+            while !piece.test_current_position(&self.game_map) && piece.y >= piece.step {
+                piece.y -= piece.step;
+            }
+        }
+    }
+
+    /// In Versus mode, receives the attack of the opponent as garbage lines, and wins once the
+    /// opponent tops out.
+    pub fn receive_attack(&mut self, opponent: &Tetris) {
+        let lines = match self.mode {
+            GameMode::Versus { ref mut received, ref mut won } => {
+                *won |= opponent.is_game_over();
+                let lines = opponent.clear_stats.attack.saturating_sub(*received);
+                *received += lines;
+                lines
+            }
+            _ => return,
+        };
+        if lines > 0 {
+            self.add_garbage(lines, VERSUS_MESSINESS);
         }
     }
 
-    pub fn create_new_tetrimino(&mut self) -> Tetrimino {
-        static mut PREV: u8 = 7;
-        let mut rand_rb = rand::random::<u8>() % 7; // TODO: let having no more than two tetrimino(now only one)
+    /// Fills the bottom of the map with `lines`, given from the top. A case is either EMPTY, the
+    /// name of a piece for a mino of its color or garbage.
+    fn load_map(&mut self, lines: &[String]) {
+        let mut game_map = vec![vec![0; MAP_WIDTH]; MAP_HEIGHT];
+        let top = MAP_HEIGHT - lines.len();
+
+        for (line_nb, line) in lines.iter().enumerate() {
+            for (case_nb, name) in line.chars().enumerate() {
+                game_map[top + line_nb][case_nb] = match name {
+                    EMPTY | ' ' => 0,
+                    name => self.rules.piece_set.position(name).map(|index| index as u8 + 1).unwrap_or(GARBAGE),
+                };
+            }
+        }
+        self.fill_game_map(&game_map);
+    }
+
+    /// Replaces the starting map by a MAP_WIDTH x MAP_HEIGHT one, the replay starts from it too.
+    pub fn set_game_map(&mut self, game_map: &[Vec<u8>]) {
+        self.replay.game_map = Some(game_map.to_vec());
+        self.fill_game_map(game_map);
+    }
+
+    /// Replaces the game map by a MAP_WIDTH x MAP_HEIGHT one, scaled to the size of the minos.
+    fn fill_game_map(&mut self, game_map: &[Vec<u8>]) {
+        let scale = self.rules.scale();
+
+        for (y, line) in self.game_map.iter_mut().enumerate() {
+            for (x, case) in line.iter_mut().enumerate() {
+                *case = game_map[y / scale][x / scale];
+            }
+        }
+        self.garbage_lines.iter_mut().for_each(|garbage| *garbage = false);
+        self.locked_at.iter_mut().for_each(|line| line.iter_mut().for_each(|locked_at| *locked_at = self.frame));
+    }
+
+    pub fn held_piece(&self) -> Option<char> {
+        self.held_piece
+    }
+
+    /// Whether the current piece can be put aside.
+    pub fn can_hold(&self) -> bool {
+        self.rules.hold && !self.hold_used && self.current_piece.is_some()
+    }
 
-        if unsafe { PREV } == rand_rb {
-            rand_rb = rand::random::<u8>() % 7;
+    /// Puts the current piece aside and takes the one which was held, or the next one if no piece
+    /// was held yet.
+    pub fn hold(&mut self) {
+        if !self.rules.hold || self.hold_used {
+            return;
         }
+        let piece = match self.current_piece.take() {
+            Some(piece) => piece,
+            None => return,
+        };
 
-        unsafe { PREV = rand_rb; }
+        match self.held_piece.replace(piece.name).and_then(|name| self.rules.piece_set.position(name)) {
+            Some(index) => {
+                let piece = self.rules.piece_set.create(index).scaled(self.rules.scale());
+                self.gravity = 0;
+                self.lock_frames = 0;
+                if !piece.test_current_position(&self.game_map) {
+                    self.game_over = true;
+                }
+                self.finesse_start = Some(piece.clone());
+                self.piece_keys = 0;
+                self.current_piece = Some(piece);
+            }
+            None => {
+                if !self.spawn_piece() {
+                    self.game_over = true;
+                }
+            }
+        }
+        self.hold_used = true;
+    }
+
+    /// Puts a new piece at the top of the map. Returns false if it doesn't fit, except in Zen
+    /// mode where the top of the map is cleared instead, or if there's no piece left in a puzzle.
+    pub fn spawn_piece(&mut self) -> bool {
+        let piece = match next_piece(&mut self.mode, &mut self.random, &mut self.previous_piece, &self.rules) {
+            Some(piece) => piece,
+            None => return false,
+        };
+        self.gravity = 0;
+        self.lock_frames = 0;
+        self.soft_drop_rows = 0;
+        if let GameMode::Master(ref mut master) = self.mode {
+            master.on_piece_spawn();
+        }
 
-        match rand_rb {
-            0 => TetriminoI::new(),
-            1 => TetriminoJ::new(),
-            2 => TetriminoL::new(),
-            3 => TetriminoO::new(),
-            4 => TetriminoS::new(),
-            5 => TetriminoZ::new(),
-            6 => TetriminoT::new(),
-            _ => unreachable!()
+        if !piece.test_current_position(&self.game_map) {
+            if let GameMode::Zen = self.mode {
+                for line in self.game_map.iter_mut().take(ZEN_CLEARED_LINES * self.rules.scale()) {
+                    line.iter_mut().for_each(|case| *case = 0);
+                }
+            } else {
+                return false;
+            }
         }
+        self.finesse_start = Some(piece.clone());
+        self.piece_keys = 0;
+        self.current_piece = Some(piece);
+        true
+    }
+
+    /// Names of the next pieces to spawn, up to `nb_pieces`, without drawing them.
+    pub fn preview(&self, nb_pieces: usize) -> Vec<char> {
+        let mut mode = self.mode.clone();
+        let mut random = self.random.clone();
+        let mut previous_piece = self.previous_piece;
+
+        (0..nb_pieces)
+            .map_while(|_| next_piece(&mut mode, &mut random, &mut previous_piece, &self.rules))
+            .map(|piece| piece.name)
+            .collect()
     }
 
-    fn check_lines(&mut self) {
+    /// Removes the complete lines from the game map and returns how many lines of minos were
+    /// removed.
+    fn remove_complete_lines(&mut self) -> u32 {
         let mut y = 0;
-        let mut score_add = 0;
+        let mut cleared = 0;
 
         while y < self.game_map.len() {
             let mut complete = true;
@@ -70,30 +664,164 @@ impl Tetris {
                 }
             }
 
-            if complete == true {
-                score_add += self.current_level;
+            if complete {
                 self.game_map.remove(y);
-                y -= 1;
+                self.garbage_lines.remove(y);
+                self.locked_at.remove(y);
+                cleared += 1;
+            } else {
+                y += 1;
             }
-            y += 1;
         }
 
-        if self.game_map.len() == 0 {
-            // A "tetris"!
+        let (width, height) = (MAP_WIDTH * self.rules.scale(), MAP_HEIGHT * self.rules.scale());
+        while self.game_map.len() < height {
+            // ToDo adding score
+            self.game_map.insert(0, vec![0; width]);
+            self.garbage_lines.insert(0, false);
+            self.locked_at.insert(0, vec![0; width]);
+        }
+        if cleared > 0 {
+            self.last_clear = Some(self.frame);
+        }
+
+        // in big mode, each line of minos is made of several lines of cases
+        cleared / self.rules.scale() as u32
+    }
+
+    /// Clears the complete lines and returns how many were cleared, not counting the cascade.
+    fn check_lines(&mut self, t_spin: bool) -> u32 {
+        let mut score_add = 0;
+        let cleared = self.remove_complete_lines();
+
+        for _ in 0..cleared {
+            score_add += self.current_level;
+            self.increase_line();
+        }
+
+        let all_clear = cleared > 0 && self.game_map.iter().all(|line| line.iter().all(|case| *case == 0));
+        if all_clear {
             score_add += 1000;
         }
+        if let GameMode::Master(ref mut master) = self.mode {
+            score_add = master.on_piece_locked(cleared, all_clear, self.soft_drop_rows, self.score,
+                                               self.frame);
+        }
         self.update_score(score_add);
+        self.clear_stats.add_clear(cleared, t_spin, all_clear);
+        if let GameMode::Puzzle(ref mut puzzle) = self.mode {
+            puzzle.on_piece_locked(self.nb_lines, cleared, t_spin, all_clear);
+        }
+        self.mode.on_lines_cleared(self.nb_lines, self.frame);
 
-        while self.game_map.len() < 16 {
-            // ToDo adding score
-            self.game_map.insert(0, vec![0; 10])
+        if self.rules.cascade && cleared > 0 {
+            self.cascade();
+        }
+        cleared
+    }
+
+    /// Lets the groups of minos fall after a line clear, as long as it causes new clears. Every
+    /// clear of the chain is worth more than the previous one.
+    fn cascade(&mut self) {
+        let mut chain = 1;
+
+        loop {
+            self.settle_groups();
+            let cleared = self.remove_complete_lines();
+            if cleared == 0 {
+                break;
+            }
+
+            chain += 1;
+            for _ in 0..cleared {
+                self.update_score(self.current_level * chain);
+                self.increase_line();
+            }
+            self.clear_stats.add_chain(chain);
+            self.mode.on_lines_cleared(self.nb_lines, self.frame);
+        }
+    }
+
+    /// Makes every group of connected minos fall until it rests on the floor or on another group.
+    fn settle_groups(&mut self) {
+        let height = self.game_map.len();
+
+        loop {
+            let (groups, labels) = self.find_groups();
+            let mut moved = false;
+
+            for (label, group) in groups.iter().enumerate() {
+                let can_fall = group.iter().all(|&(x, y)| {
+                    y + 1 < height && (self.game_map[y + 1][x] == 0 || labels[y + 1][x] == Some(label))
+                });
+                if !can_fall {
+                    continue;
+                }
+
+                // the cases are sorted from the bottom, so they don't overwrite each other
+                for &(x, y) in group.iter() {
+                    self.game_map[y + 1][x] = self.game_map[y][x];
+                    self.locked_at[y + 1][x] = self.locked_at[y][x];
+                    self.game_map[y][x] = 0;
+                }
+                moved = true;
+            }
+
+            if !moved {
+                break;
+            }
+        }
+    }
+
+    /// Returns the groups of connected cases of the game map, each sorted from the bottom, and
+    /// the group every case belongs to.
+    fn find_groups(&self) -> (Vec<Group>, Vec<Vec<Option<usize>>>) {
+        let (width, height) = (self.game_map[0].len(), self.game_map.len());
+        let mut labels = vec![vec![None; width]; height];
+        let mut groups = Vec::new();
+
+        for start_y in 0..height {
+            for start_x in 0..width {
+                if self.game_map[start_y][start_x] == 0 || labels[start_y][start_x].is_some() {
+                    continue;
+                }
+
+                let label = groups.len();
+                let mut group = Vec::new();
+                let mut to_visit = vec![(start_x, start_y)];
+                labels[start_y][start_x] = Some(label);
+
+                while let Some((x, y)) = to_visit.pop() {
+                    group.push((x, y));
+                    let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+                    for &(nx, ny) in neighbours.iter() {
+                        if nx < width && ny < height && self.game_map[ny][nx] != 0 && labels[ny][nx].is_none() {
+                            labels[ny][nx] = Some(label);
+                            to_visit.push((nx, ny));
+                        }
+                    }
+                }
+                group.sort_by_key(|&(_, y)| Reverse(y));
+                groups.push(group);
+            }
         }
+        (groups, labels)
     }
 
     pub fn make_permanent(&mut self) {
         let mut to_add = 0;
+        let mut t_spin = false;
+        let mut name = ' ';
+        let mut cells = Vec::new();
 
         if let Some(ref mut piece) = self.current_piece {
+            name = piece.name;
+            t_spin = piece.is_t_spin(&self.game_map);
+            // the spins and tucks aren't held against the player
+            if let Some(min_keys) = self.finesse_start.as_ref()
+                .and_then(|start| finesse::min_keys(&self.game_map, start, piece)) {
+                self.finesse_faults += self.piece_keys.saturating_sub(min_keys);
+            }
             let mut shift_y = 0;
 
             // check if we are not ran out of Tetrimino boundary or game map at Y axis
@@ -108,17 +836,115 @@ impl Tetris {
                         let x = piece.x + shift_x as isize;
                         self.game_map[piece.y + shift_y][x as usize] =
                             piece.states[piece.current_state as usize][shift_y][shift_x];
+                        self.locked_at[piece.y + shift_y][x as usize] = self.frame;
+                        cells.push((x as usize, piece.y + shift_y));
                     }
                     shift_x += 1;
                 }
                 shift_y += 1;
             }
-            to_add += self.current_level;
+            if !matches!(self.mode, GameMode::Master(_)) {
+                to_add += self.current_level;
+            }
+            self.nb_pieces += 1;
         }
 
         self.update_score(to_add);
-        self.check_lines(); // After Tetrimino becomes permanent check if any line in game map is full
+        let cleared = self.check_lines(t_spin); // After Tetrimino becomes permanent check if any line in game map is full
+        if let GameMode::Practice(ref mut practice) = self.mode {
+            // the placements are checked in minos, not in cases
+            let scale = self.rules.scale();
+            let mut minos = cells.iter().map(|&(x, y)| (x / scale, y / scale)).collect::<Vec<_>>();
+            minos.sort();
+            minos.dedup();
+            practice.on_piece_locked(name, minos, cleared, self.held_piece, &self.rules.piece_set);
+        }
         self.current_piece = None;
+        self.are_frames = 0;
+        self.hold_used = false;
+
+        if let Some(state) = self.spawn_state.take() {
+            if self.undo_stack.len() >= UNDO_LIMIT {
+                self.undo_stack.remove(0);
+            }
+            self.undo_stack.push(state);
+            self.redo_stack.clear();
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            game_map: self.game_map.clone(),
+            current_level: self.current_level,
+            score: self.score,
+            nb_lines: self.nb_lines,
+            nb_pieces: self.nb_pieces,
+            clear_stats: self.clear_stats.clone(),
+            current_piece: self.current_piece.clone(),
+            mode: self.mode.clone(),
+            random: self.random.clone(),
+            previous_piece: self.previous_piece,
+            garbage_random: self.garbage_random.clone(),
+            garbage_lines: self.garbage_lines.clone(),
+            garbage_hole: self.garbage_hole,
+            locked_at: self.locked_at.clone(),
+            held_piece: self.held_piece,
+        }
+    }
+
+    fn restore(&mut self, state: Snapshot) {
+        if state.current_piece.is_some() {
+            self.spawn_state = Some(state.clone());
+        }
+        self.game_map = state.game_map;
+        self.current_level = state.current_level;
+        self.score = state.score;
+        self.nb_lines = state.nb_lines;
+        self.nb_pieces = state.nb_pieces;
+        self.clear_stats = state.clear_stats;
+        self.current_piece = state.current_piece;
+        self.mode = state.mode;
+        self.random = state.random;
+        self.previous_piece = state.previous_piece;
+        self.garbage_random = state.garbage_random;
+        self.garbage_lines = state.garbage_lines;
+        self.garbage_hole = state.garbage_hole;
+        self.locked_at = state.locked_at;
+        self.held_piece = state.held_piece;
+        self.finesse_start = self.current_piece.clone();
+        self.piece_keys = 0;
+        self.gravity = 0;
+        self.lock_frames = 0;
+        self.are_frames = 0;
+        self.soft_drop_rows = 0;
+        self.hold_used = false;
+        self.game_over = false;
+    }
+
+    /// Goes back to the spawn of the last piece made permanent. Returns false if there's no
+    /// placement to take back.
+    pub fn undo(&mut self) -> bool {
+        let state = match self.undo_stack.pop() {
+            Some(state) => state,
+            None => return false,
+        };
+        let current = self.spawn_state.take().unwrap_or_else(|| self.snapshot());
+        self.redo_stack.push(current);
+        self.restore(state);
+        true
+    }
+
+    /// Plays again the last placement taken back. Returns false if there's none.
+    pub fn redo(&mut self) -> bool {
+        let state = match self.redo_stack.pop() {
+            Some(state) => state,
+            None => return false,
+        };
+        if let Some(current) = self.spawn_state.take() {
+            self.undo_stack.push(current);
+        }
+        self.restore(state);
+        true
     }
 
     fn update_score(&mut self, to_add: u32) {
@@ -131,18 +957,32 @@ impl Tetris {
 
     fn increase_line(&mut self) {
         self.nb_lines += 1;
-        if self.nb_lines > LEVEL_LINES[self.current_level as usize - 1] {
-            self.increase_level();
+        if let Some(&lines) = LEVEL_LINES.get(self.current_level as usize - 1) {
+            if self.nb_lines > lines {
+                self.increase_level();
+            }
         }
     }
 }
 
-pub fn is_time_over(tetris: &mut Tetris, timer: &SystemTime) -> bool {
-    match timer.elapsed() {
-        Ok(elapsed) => {
-            let millis = elapsed.as_secs() as u32 * 1000 + elapsed.subsec_nanos() / 1_000_000;
-            millis > LEVEL_TIMES[tetris.current_level as usize - 1]
+/// Draws the next piece to spawn, from the queue of the mode or at random. Returns None if there's
+/// no piece left in a puzzle.
+fn next_piece(mode: &mut GameMode, random: &mut Random, previous_piece: &mut u32,
+              rules: &Rules) -> Option<Tetrimino> {
+    let index = match *mode {
+        GameMode::Puzzle(ref mut puzzle) => rules.piece_set.position(puzzle.next_piece()?)?,
+        GameMode::Practice(ref mut practice) =>
+            rules.piece_set.position(practice.next_piece(random, &rules.piece_set))?,
+        _ => {
+            let nb_pieces = rules.piece_set.pieces.len() as u32;
+            let mut rand_rb = random.below(nb_pieces); // TODO: let having no more than two tetrimino(now only one)
+
+            if *previous_piece == rand_rb {
+                rand_rb = random.below(nb_pieces);
+            }
+            *previous_piece = rand_rb;
+            rand_rb as usize
         }
-        Err(_) => false
-    }
+    };
+    Some(rules.piece_set.create(index).scaled(rules.scale()))
 }